@@ -0,0 +1,307 @@
+use std::io;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use log::{info, debug};
+use crate::command::{CommandExecutor, ProxyCommandExecutor};
+use crate::packet::frame::RawPacket;
+use crate::packet::play::{CommandNode, DeclareCommands, ServerboundChat, TabCompleteRequest, TabCompleteResponse, NODE_LITERAL};
+use crate::packet::registry::{self, Direction, Kind, State};
+use crate::packet::version;
+use crate::player::Player;
+use crate::protocol::relay::{Handler, Request};
+use uuid::Uuid;
+
+/// Runs the proxy's own commands when players type them, like `/server lobby`, and answers Tab
+/// presses in them, so `/server <tab>` lists the servers in the config. Everything else is left to
+/// the backend.
+///
+/// Clients from 1.13 on only know the commands the backend declares to them, so the proxy's are
+/// added to those, asking us for completions. Older clients have the backend complete labels,
+/// and the proxy's matching labels are added to its answer.
+pub struct CommandHandler {
+    executor: Arc<ProxyCommandExecutor>,
+    /// Our labels matching what a pre-1.13 player is completing, waiting for the backend's answer.
+    labels: Mutex<HashMap<Uuid, Vec<String>>>
+}
+
+#[async_trait]
+impl Handler for CommandHandler {
+    fn interests(&self) -> Vec<Kind> {
+        vec![Kind::ServerboundChat, Kind::TabCompleteRequest, Kind::TabCompleteResponse, Kind::DeclareCommands]
+    }
+
+    async fn handle(&self, player: &Player, direction: Direction, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>> {
+        match registry::lookup(State::Play, direction, version, packet.id) {
+            Some(Kind::ServerboundChat) => self.handle_chat(player, packet, version).await,
+            Some(Kind::TabCompleteRequest) => self.handle_tab_complete(player, packet, version).await,
+            Some(Kind::TabCompleteResponse) => self.add_labels(player, packet, version).await,
+            Some(Kind::DeclareCommands) => self.declare_commands(player, packet, version).await,
+            _ => Ok(Some(packet))
+        }
+    }
+
+    fn disconnected(&self, player: &Player) {
+        self.labels.lock().unwrap().remove(&player.id);
+    }
+}
+
+impl CommandHandler {
+    pub fn new(executor: Arc<ProxyCommandExecutor>) -> Self {
+        CommandHandler {
+            executor,
+            labels: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Whether `label` belongs to a command `player` can use. Console-only commands and those
+    /// they weren't granted are left to the backend, which might have one by the same label.
+    fn is_ours(&self, player: &Player, label: &str) -> bool {
+        match self.executor.get_command(label) {
+            Some(command) => self.executor.can_use(player, command),
+            None => false
+        }
+    }
+
+    async fn handle_chat(&self, player: &Player, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>> {
+        let chat = packet.decode::<ServerboundChat>(version).await?;
+
+        let command = match chat.message.strip_prefix('/') {
+            Some(command) => command,
+            None => return Ok(Some(packet))
+        };
+
+        // checked before parsing, a backend command rift can't tokenize is still the backend's
+        let label = command.split_whitespace().next().unwrap_or_default();
+        if !self.is_ours(player, label) {
+            return Ok(Some(packet));
+        }
+
+        info!("{} issued a proxy command: /{}", player.name, command);
+        self.executor.parse(Box::new(player.clone()), command);
+
+        Ok(None)
+    }
+
+    async fn handle_tab_complete(&self, player: &Player, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>> {
+        let request = packet.decode::<TabCompleteRequest>(version).await?;
+
+        let command = match request.text.strip_prefix('/') {
+            Some(command) => command,
+            None => return Ok(Some(packet))
+        };
+
+        // the label itself is completed by the backend, which knows all of its own commands too
+        let label = match command.split_once(char::is_whitespace) {
+            Some((label, _)) => label,
+            None => {
+                if version < version::V1_13 {
+                    let labels = self.executor.suggest(player, command).into_iter()
+                        .map(|label| format!("/{}", label))
+                        .collect();
+                    self.labels.lock().unwrap().insert(player.id, labels);
+                }
+
+                return Ok(Some(packet));
+            }
+        };
+
+        if !self.is_ours(player, label) {
+            return Ok(Some(packet));
+        }
+
+        let matches = self.executor.suggest(player, command);
+
+        // before 1.13 the client swaps the matches in for the last word itself, after that they
+        // replace the range given here
+        let partial = command.rsplit(char::is_whitespace).next().unwrap_or_default();
+        let length = partial.encode_utf16().count() as i32;
+        let response = TabCompleteResponse {
+            transaction_id: request.transaction_id,
+            start: request.text.encode_utf16().count() as i32 - length,
+            length,
+            matches
+        };
+
+        // a client that's spamming Tab just doesn't hear back about all of it
+        if let Err(error) = player.request(Request::Send(RawPacket::encode(response, version).await?)) {
+            debug!("Couldn't answer {}'s tab completion: {}", player.name, error);
+        }
+
+        Ok(None)
+    }
+
+    /// Adds our labels to the backend's answer to a pre-1.13 player completing a command label.
+    async fn add_labels(&self, player: &Player, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>> {
+        let labels = match self.labels.lock().unwrap().remove(&player.id) {
+            Some(labels) => labels,
+            None => return Ok(Some(packet))
+        };
+
+        let mut response = packet.decode::<TabCompleteResponse>(version).await?;
+        response.matches.extend(labels);
+        response.matches.sort_by_key(|label| label.to_lowercase());
+        response.matches.dedup();
+
+        Ok(Some(RawPacket::encode(response, version).await?))
+    }
+
+    /// Adds the commands the player can use to the ones the backend declares, taking the place of
+    /// any the backend has by the same label, which it would never get to run anyway. Everything
+    /// after the label is left for us to complete.
+    async fn declare_commands(&self, player: &Player, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>> {
+        let mut commands = match packet.decode::<DeclareCommands>(version).await {
+            Ok(commands) => commands,
+            Err(error) => {
+                // e.g. an argument from a mod we can't read past, the player just won't see ours
+                debug!("Couldn't add the proxy's commands to {}'s: {}", player.name, error);
+                return Ok(Some(packet));
+            }
+        };
+
+        let labels = self.executor.labels(player);
+        let root = commands.root as usize;
+
+        let nodes = &commands.nodes;
+        let mut children: Vec<i32> = match nodes.get(root) {
+            Some(node) => node.children.iter()
+                .copied()
+                .filter(|child| {
+                    let name = nodes.get(*child as usize)
+                        .filter(|node| node.kind() == NODE_LITERAL)
+                        .and_then(|node| node.name.as_deref());
+
+                    !name.is_some_and(|name| labels.iter().any(|label| label.eq_ignore_ascii_case(name)))
+                })
+                .collect(),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Declared commands are missing their root."))
+        };
+
+        for label in labels {
+            let arguments = commands.nodes.len() as i32;
+            commands.nodes.push(CommandNode::greedy_argument("arguments"));
+
+            children.push(commands.nodes.len() as i32);
+            commands.nodes.push(CommandNode::literal(label, vec![arguments]));
+        }
+
+        commands.nodes[root].children = children;
+
+        Ok(Some(RawPacket::encode(commands, version).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::command::proxy::ProxyCommand;
+    use crate::command::server::ServerCommand;
+    use crate::command::testing::server;
+    use crate::packet::play::NODE_ROOT;
+
+    fn handler(permitted: bool) -> CommandHandler {
+        let commands: Vec<Box<dyn Command + Send + Sync>> = vec![Box::new(ProxyCommand::default()), Box::new(ServerCommand::default())];
+        CommandHandler::new(Arc::new(ProxyCommandExecutor::new(commands, Box::new(server(permitted)))))
+    }
+
+    fn declared() -> DeclareCommands {
+        DeclareCommands {
+            nodes: vec![
+                CommandNode {
+                    flags: NODE_ROOT,
+                    children: vec![1, 2],
+                    redirect: None,
+                    name: None,
+                    parser: None,
+                    suggestions: None
+                },
+                CommandNode::literal("help", Vec::new()),
+                CommandNode::literal("Server", Vec::new())
+            ],
+            root: 0
+        }
+    }
+
+    fn names(commands: &DeclareCommands, node: i32) -> Vec<String> {
+        let mut names: Vec<String> = commands.nodes[node as usize].children.iter()
+            .filter_map(|child| commands.nodes[*child as usize].name.to_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn declares_our_commands_in_place_of_the_backends() {
+        let packet = RawPacket::encode(declared(), version::V1_16).await.unwrap();
+        let packet = handler(true).handle(&Player::offline("Notch"), Direction::Clientbound, packet, version::V1_16).await.unwrap().unwrap();
+        let commands = packet.decode::<DeclareCommands>(version::V1_16).await.unwrap();
+
+        assert_eq!(names(&commands, commands.root), ["help", "proxy", "rift", "server"]);
+
+        let server = commands.nodes[0].children.iter()
+            .copied()
+            .find(|child| commands.nodes[*child as usize].name.as_deref() == Some("server"))
+            .unwrap();
+        let arguments = &commands.nodes[commands.nodes[server as usize].children[0] as usize];
+        assert_eq!(arguments.suggestions.as_deref(), Some("minecraft:ask_server"));
+    }
+
+    #[tokio::test]
+    async fn adds_our_labels_to_what_the_backend_completes() {
+        let handler = handler(true);
+        let player = Player::offline("Notch");
+        let version = version::V1_12_2;
+
+        let request = RawPacket::encode(TabCompleteRequest {
+            transaction_id: 0,
+            text: String::from("/se"),
+            assume_command: false,
+            position: None
+        }, version).await.unwrap();
+        assert!(handler.handle(&player, Direction::Serverbound, request, version).await.unwrap().is_some());
+
+        let response = RawPacket::encode(TabCompleteResponse {
+            transaction_id: 0,
+            start: 0,
+            length: 0,
+            matches: vec![String::from("/seed"), String::from("/setblock")]
+        }, version).await.unwrap();
+        let response = handler.handle(&player, Direction::Clientbound, response, version).await.unwrap().unwrap();
+
+        assert_eq!(response.decode::<TabCompleteResponse>(version).await.unwrap().matches, ["/seed", "/server", "/setblock"]);
+    }
+
+    #[tokio::test]
+    async fn forgets_labels_of_players_who_left() {
+        let handler = handler(true);
+        let player = Player::offline("Notch");
+        let version = version::V1_12_2;
+
+        let request = RawPacket::encode(TabCompleteRequest {
+            transaction_id: 0,
+            text: String::from("/se"),
+            assume_command: false,
+            position: None
+        }, version).await.unwrap();
+        handler.handle(&player, Direction::Serverbound, request, version).await.unwrap();
+        assert!(handler.labels.lock().unwrap().contains_key(&player.id));
+
+        handler.disconnected(&player);
+        assert!(handler.labels.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn leaves_commands_players_were_not_granted_to_the_backend() {
+        let handler = handler(false);
+        let player = Player::offline("Notch");
+
+        let packet = RawPacket::encode(declared(), version::V1_16).await.unwrap();
+        let packet = handler.handle(&player, Direction::Clientbound, packet, version::V1_16).await.unwrap().unwrap();
+        let commands = packet.decode::<DeclareCommands>(version::V1_16).await.unwrap();
+        assert_eq!(names(&commands, commands.root), ["Server", "help"]);
+
+        let chat = RawPacket::encode(ServerboundChat { message: String::from("/server hub") }, version::V1_16).await.unwrap();
+        assert!(handler.handle(&player, Direction::Serverbound, chat, version::V1_16).await.unwrap().is_some());
+    }
+}
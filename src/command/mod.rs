@@ -1,30 +1,243 @@
 pub mod proxy;
+pub mod server;
+pub mod tree;
+pub mod handler;
+#[cfg(test)]
+pub(crate) mod testing;
 use std::io;
+use std::collections::HashMap;
 use crate::server::Server;
+use crate::player::Player;
+use crate::packet::Chat;
 
 pub trait CommandSender {
-    fn send_message(&self, message: String);
+    fn send_message(&self, message: Chat);
     fn get_name(&self) -> &str;
+
+    /// Whether this is the proxy's console rather than a player.
+    fn is_console(&self) -> bool {
+        false
+    }
+
+    /// The player sending the command, `None` for the console.
+    fn as_player(&self) -> Option<&Player> {
+        None
+    }
 }
 
 pub trait CommandExecutor {
-    fn parse(sender: Box<dyn CommandSender>, command: String);
+    /// Runs `command`, a line like `proxy list` without any leading slash, as `sender`. Returns
+    /// `false` if there's no command by that label or alias, leaving it to the caller to say so.
+    fn parse(&self, sender: Box<dyn CommandSender>, command: &str) -> bool;
+    /// What the last word of `command` could be completed to, as typed by `sender`. The words
+    /// before it are taken as they are.
+    fn suggest(&self, sender: &dyn CommandSender, command: &str) -> Vec<String>;
 }
 
 pub trait Command {
     fn get_label(&self) -> &'static str;
     fn get_aliases(&self) -> Vec<&'static str>;
     fn is_console_only(&self) -> bool;
+    /// What players need to be granted to use the command at all, the console has every permission.
+    fn get_permission(&self) -> &'static str;
     fn execute(&self, sender: Box<dyn CommandSender>, arguments: Vec<String>);
     fn set_backend(&mut self, server: Box<dyn Server +  Send + Sync>) -> io::Result<()>;
+
+    /// Completions for the last of `arguments`, which may be empty when nothing of it was typed yet.
+    fn suggest(&self, _sender: &dyn CommandSender, _arguments: Vec<String>) -> Vec<String> {
+        Vec::new()
+    }
 }
 
+/// Owns the registered commands and runs them for the console and players alike.
 pub struct ProxyCommandExecutor {
+    commands: Vec<Box<dyn Command + Send + Sync>>,
+    /// Decides who's got which permission.
+    server: Box<dyn Server + Send + Sync>,
+    /// Every label and alias, lowercase, to the command it belongs to.
+    labels: HashMap<String, usize>
+}
 
+impl ProxyCommandExecutor {
+    pub fn new(commands: Vec<Box<dyn Command + Send + Sync>>, server: Box<dyn Server + Send + Sync>) -> Self {
+        let mut labels = HashMap::new();
+
+        for (index, command) in commands.iter().enumerate() {
+            // aliases never take a label from another command
+            for alias in command.get_aliases() {
+                labels.entry(alias.to_lowercase()).or_insert(index);
+            }
+
+            labels.insert(command.get_label().to_lowercase(), index);
+        }
+
+        ProxyCommandExecutor {
+            commands,
+            server,
+            labels
+        }
+    }
+
+    /// The command with `label` as its label or one of its aliases.
+    pub fn get_command(&self, label: &str) -> Option<&(dyn Command + Send + Sync)> {
+        self.labels.get(&label.to_lowercase())
+            .map(|index| self.commands[*index].as_ref())
+    }
+
+    /// Every label and alias `sender` gets to use, sorted.
+    pub fn labels(&self, sender: &dyn CommandSender) -> Vec<String> {
+        let mut labels: Vec<String> = self.labels.iter()
+            .filter(|(_, index)| self.can_use(sender, self.commands[**index].as_ref()))
+            .map(|(label, _)| label.to_owned())
+            .collect();
+
+        labels.sort();
+        labels
+    }
+
+    /// Whether `sender` gets to use `command` at all.
+    pub fn can_use(&self, sender: &dyn CommandSender, command: &(dyn Command + Send + Sync)) -> bool {
+        (!command.is_console_only() || sender.is_console()) && self.server.has_permission(sender, command.get_permission())
+    }
 }
 
 impl CommandExecutor for ProxyCommandExecutor {
-    fn parse(sender: Box<dyn CommandSender>, command: String) {
+    fn parse(&self, sender: Box<dyn CommandSender>, command: &str) -> bool {
+        let mut arguments = match tokenize(command) {
+            Ok(arguments) => arguments.into_iter(),
+            Err(error) => {
+                sender.send_message(Chat::new(format!("&c{}", error)));
+                return true;
+            }
+        };
+
+        let command = match arguments.next().and_then(|label| self.get_command(&label)) {
+            Some(command) => command,
+            None => return false
+        };
+
+        if command.is_console_only() && !sender.is_console() {
+            sender.send_message(Chat::new("&cThis command can only be used from the console."));
+            return true;
+        }
+
+        if !self.can_use(sender.as_ref(), command) {
+            sender.send_message(Chat::new("&cYou don't have permission to use this command."));
+            return true;
+        }
+
+        command.execute(sender, arguments.collect());
+        true
+    }
+
+    fn suggest(&self, sender: &dyn CommandSender, command: &str) -> Vec<String> {
+        let mut arguments = match tokenize(command) {
+            Ok(arguments) => arguments,
+            Err(_) => return Vec::new()
+        };
+
+        // a trailing space means the next word was started but nothing of it typed yet
+        if arguments.is_empty() || command.ends_with(char::is_whitespace) {
+            arguments.push(String::new());
+        }
 
+        if arguments.len() == 1 {
+            let partial = arguments[0].to_lowercase();
+            return self.labels(sender).into_iter()
+                .filter(|label| label.starts_with(&partial))
+                .collect();
+        }
+
+        let mut arguments = arguments.into_iter();
+        match arguments.next().and_then(|label| self.get_command(&label)) {
+            Some(command) if self.can_use(sender, command) => command.suggest(sender, arguments.collect()),
+            _ => Vec::new()
+        }
+    }
+}
+
+/// Splits a command line on whitespace, keeping what's in single or double quotes together.
+/// Backslashes escape the character after them.
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    // quotes make a token even when there's nothing between them
+    let mut started = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => match chars.next() {
+                Some(escaped) => {
+                    current.push(escaped);
+                    started = true;
+                },
+                None => return Err(String::from("Nothing to escape at the end of the command."))
+            },
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                started = true;
+            },
+            (c, Some(open)) if c == open => quote = None,
+            (c, None) if c.is_whitespace() => {
+                if started {
+                    tokens.push(std::mem::take(&mut current));
+                    started = false;
+                }
+            },
+            (c, _) => {
+                current.push(c);
+                started = true;
+            }
+        }
+    }
+
+    if let Some(quote) = quote {
+        return Err(format!("Missing closing {}.", quote));
     }
-}
\ No newline at end of file
+
+    if started {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Vec<String> {
+        tokenize(line).unwrap()
+    }
+
+    #[test]
+    fn splits_on_any_whitespace() {
+        assert_eq!(tokens("send  Notch\thub "), ["send", "Notch", "hub"]);
+        assert!(tokens("   ").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_text_together() {
+        assert_eq!(tokens(r#"alert "Restarting in 5" 'min utes'"#), ["alert", "Restarting in 5", "min utes"]);
+        assert_eq!(tokens(r#"say "it's fine""#), ["say", "it's fine"]);
+        assert_eq!(tokens(r#"a"b c"d"#), ["ab cd"]);
+    }
+
+    #[test]
+    fn quotes_make_empty_tokens() {
+        assert_eq!(tokens(r#"set "" ''"#), ["set", "", ""]);
+    }
+
+    #[test]
+    fn escapes_the_next_character() {
+        assert_eq!(tokens(r#"say \"hi\" a\ b \\"#), ["say", "\"hi\"", "a b", "\\"]);
+    }
+
+    #[test]
+    fn refuses_unfinished_lines() {
+        assert_eq!(tokenize(r#"say "hi"#).unwrap_err(), "Missing closing \".");
+        assert_eq!(tokenize("say hi\\").unwrap_err(), "Nothing to escape at the end of the command.");
+    }
+}
@@ -1,22 +1,128 @@
 use crate::command::{Command, CommandSender};
+use crate::command::tree::{self, ArgumentType, Context, Node};
 use std::io;
 use crate::server::Server;
+use crate::packet::Chat;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const LABEL: &str = "proxy";
+const ALIAS: &str = "rift";
+const LIST_PAGE_SIZE: usize = 20;
+/// Lets players use `/proxy` at all.
+pub const PERMISSION: &str = "rift.command.proxy";
+/// Lets players rotate and reload the proxy's key pair.
+pub const KEY_PERMISSION: &str = "rift.command.key";
+/// Lets players send a message to everyone online.
+pub const ALERT_PERMISSION: &str = "rift.command.alert";
+/// Lets players move other players between servers.
+pub const SEND_PERMISSION: &str = "rift.command.send";
+/// Lets players stop the proxy, the console always can.
+pub const STOP_PERMISSION: &str = "rift.command.stop";
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const LABEL: &'static str = "proxy";
-const ALIAS: &'static str = "rift";
 pub struct ProxyCommand {
-    backend: Option<Box<dyn Server + Send + Sync>>
+    backend: Option<Box<dyn Server + Send + Sync>>,
+    tree: Node
 }
 
 impl std::default::Default for ProxyCommand {
     fn default() -> Self {
         ProxyCommand {
-            backend: None
+            backend: None,
+            tree: syntax()
         }
     }
 }
 
+fn syntax() -> Node {
+    let stop = |context: &Context| {
+        context.sender.send_message(Chat::new("Stopping the proxy server.."));
+        context.server.shutdown();
+        Ok(())
+    };
+
+    let mut tree = tree::literal(LABEL)
+        .executes(|context| {
+            let players = context.server.get_players().len();
+            let servers = context.server.get_servers().len();
+
+            context.sender.send_message(Chat::new(format!("<gold>Rift {}</gold> with {} players online across {} servers.", VERSION, players, servers)));
+            Ok(())
+        })
+        .then(tree::literal("version").executes(version))
+        .then(tree::literal("ver").executes(version));
+
+    for label in &["stop", "end", "kill", "shutdown"] {
+        tree = tree.then(tree::literal(label).requires(STOP_PERMISSION).executes(stop));
+    }
+
+    tree
+        .then(tree::literal("list")
+            .executes(|context| list(context, 1))
+            .then(tree::argument("page", ArgumentType::Integer { min: 1, max: i32::MAX as i64 })
+                .executes(|context| list(context, context.get_integer("page") as usize))))
+        .then(tree::literal("alert")
+            .requires(ALERT_PERMISSION)
+            .then(tree::argument("message", ArgumentType::GreedyString).executes(|context| {
+                let message = Chat::new(context.get_string("message"));
+                for player in context.server.get_players() {
+                    player.send_message(message.clone());
+                }
+
+                context.sender.send_message(Chat::new(format!("Alerted {} players.", context.server.get_players().len())));
+                Ok(())
+            })))
+        .then(tree::literal("key")
+            .requires(KEY_PERMISSION)
+            .then(tree::literal("rotate").executes(|context| {
+                context.server.rotate_key().map_err(|error| format!("Couldn't update the RSA key pair: {}", error))?;
+                context.sender.send_message(Chat::new("Rotated the RSA key pair."));
+                Ok(())
+            }))
+            .then(tree::literal("reload").executes(|context| {
+                context.server.reload_key().map_err(|error| format!("Couldn't update the RSA key pair: {}", error))?;
+                context.sender.send_message(Chat::new("Reloaded the RSA key pair."));
+                Ok(())
+            })))
+        .then(tree::literal("send")
+            .requires(SEND_PERMISSION)
+            .then(tree::argument("player", ArgumentType::Player)
+                .then(tree::argument("server", ArgumentType::Server).executes(|context| {
+                    let player = context.get_player("player");
+                    let server = context.get_server("server");
+
+                    context.server.switch_server(player, server.id).map_err(|error| error.to_string())?;
+                    context.sender.send_message(Chat::new(format!("Sending {} to {}.", player.name, server.id)));
+                    Ok(())
+                }))))
+}
+
+/// Shows a page of `LIST_PAGE_SIZE` online players, counting from 1.
+fn list(context: &Context, page: usize) -> Result<(), String> {
+    let mut names: Vec<String> = context.server.get_players().into_iter()
+        .map(|player| player.name)
+        .collect();
+    names.sort_by_key(|name| name.to_lowercase());
+
+    let pages = names.len().div_ceil(LIST_PAGE_SIZE).max(1);
+    if page > pages {
+        return Err(format!("There are only {} pages of players.", pages));
+    }
+
+    let shown = names.chunks(LIST_PAGE_SIZE).nth(page - 1).unwrap_or_default().join(", ");
+    if pages == 1 {
+        context.sender.send_message(Chat::new(format!("Players ({}): {}", names.len(), shown)));
+    } else {
+        context.sender.send_message(Chat::new(format!("Players ({}), page {} of {}: {}", names.len(), page, pages, shown)));
+    }
+
+    Ok(())
+}
+
+fn version(context: &Context) -> Result<(), String> {
+    context.sender.send_message(Chat::new(format!("Rift version {}", VERSION)));
+    Ok(())
+}
+
 impl Command for ProxyCommand {
     fn get_label(&self) -> &'static str {
         LABEL
@@ -30,38 +136,25 @@ impl Command for ProxyCommand {
         false
     }
 
+    fn get_permission(&self) -> &'static str {
+        PERMISSION
+    }
+
     fn set_backend(&mut self, server: Box<dyn Server + Send + Sync>) -> io::Result<()> {
         self.backend = Some(server);
         Ok(())
     }
 
-    fn execute(&self, sender: Box<dyn CommandSender>, mut arguments: Vec<String>) {
-       if arguments.is_empty() {
-           sender.send_message(format!("You are on proxy {}.", "Test"));
-           return;
-       }
-
-       match arguments.pop() {
-           Some(arg) => {
-               match arg.to_lowercase().as_ref() {
-                   "version" | "ver" => {
-                    sender.send_message(format!("Rift version {}", VERSION));
-                   },
-
-                   "stop" | "end" | "kill" | "shutdown" => {
-                    sender.send_message(String::from("Stopping the proxy server.."));
-                   }
-
-                   "list" => {
-                       sender.send_message(String::from("Players: "))
-                   }
-                   
-                   _ => {
-                    sender.send_message(String::from("Unknown proxy command."));
-                   }
-               }
-           },
-           _ => {}
-       }
+    fn execute(&self, sender: Box<dyn CommandSender>, arguments: Vec<String>) {
+        if let Some(backend) = &self.backend {
+            self.tree.execute(sender.as_ref(), backend.as_ref(), arguments);
+        }
+    }
+
+    fn suggest(&self, sender: &dyn CommandSender, arguments: Vec<String>) -> Vec<String> {
+        match &self.backend {
+            Some(backend) => self.tree.suggest(sender, backend.as_ref(), arguments),
+            None => Vec::new()
+        }
     }
-}
\ No newline at end of file
+}
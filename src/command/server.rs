@@ -0,0 +1,79 @@
+use crate::command::{Command, CommandSender};
+use crate::command::tree::{self, ArgumentType, Node};
+use std::io;
+use crate::server::Server;
+use crate::packet::Chat;
+
+const LABEL: &str = "server";
+/// Lets players use `/server`.
+pub const PERMISSION: &str = "rift.command.server";
+
+/// `/server` lists the servers players can go to, `/server <server>` takes them there.
+pub struct ServerCommand {
+    backend: Option<Box<dyn Server + Send + Sync>>,
+    tree: Node
+}
+
+impl std::default::Default for ServerCommand {
+    fn default() -> Self {
+        ServerCommand {
+            backend: None,
+            tree: syntax()
+        }
+    }
+}
+
+fn syntax() -> Node {
+    tree::literal(LABEL)
+        .executes(|context| {
+            let servers: Vec<String> = context.server.get_servers().into_iter()
+                .map(|server| format!("<hover:show_text:'Click to connect'><click:run_command:/{} {}>{}</click></hover>", LABEL, server.id, server.id))
+                .collect();
+
+            context.sender.send_message(Chat::new(format!("<gold>Servers: <white>{}", servers.join("<gray>, </gray>"))));
+            Ok(())
+        })
+        .then(tree::argument("server", ArgumentType::Server).executes(|context| {
+            let player = context.sender.as_player()
+                .ok_or_else(|| String::from("Only players can be sent to a server."))?;
+
+            context.server.switch_server(player, context.get_server("server").id)
+                .map_err(|error| error.to_string())
+        }))
+}
+
+impl Command for ServerCommand {
+    fn get_label(&self) -> &'static str {
+        LABEL
+    }
+
+    fn get_aliases(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    fn is_console_only(&self) -> bool {
+        false
+    }
+
+    fn get_permission(&self) -> &'static str {
+        PERMISSION
+    }
+
+    fn set_backend(&mut self, server: Box<dyn Server + Send + Sync>) -> io::Result<()> {
+        self.backend = Some(server);
+        Ok(())
+    }
+
+    fn execute(&self, sender: Box<dyn CommandSender>, arguments: Vec<String>) {
+        if let Some(backend) = &self.backend {
+            self.tree.execute(sender.as_ref(), backend.as_ref(), arguments);
+        }
+    }
+
+    fn suggest(&self, sender: &dyn CommandSender, arguments: Vec<String>) -> Vec<String> {
+        match &self.backend {
+            Some(backend) => self.tree.suggest(sender, backend.as_ref(), arguments),
+            None => Vec::new()
+        }
+    }
+}
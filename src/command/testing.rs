@@ -0,0 +1,86 @@
+//! Stand-ins for the proxy and whoever runs commands, for testing commands without either.
+
+use std::io;
+use std::net;
+use std::sync::{Arc, Mutex};
+use crate::command::CommandSender;
+use crate::config::ServerConfig;
+use crate::packet::Chat;
+use crate::player::Player;
+use crate::server::Server;
+use crate::util::key::KeyPair;
+
+pub(crate) struct TestServer {
+    players: Vec<Player>,
+    servers: Vec<ServerConfig>,
+    keys: Arc<KeyPair>,
+    permitted: bool
+}
+
+impl Server for TestServer {
+    fn get_players(&self) -> Vec<Player> {
+        self.players.clone()
+    }
+
+    fn get_player(&self, name: &str) -> Option<Player> {
+        self.players.iter().find(|player| player.name.eq_ignore_ascii_case(name)).cloned()
+    }
+
+    fn get_addresses(&self) -> Vec<net::SocketAddr> {
+        Vec::new()
+    }
+
+    fn get_key_pair(&self) -> Arc<KeyPair> {
+        self.keys.clone()
+    }
+
+    fn rotate_key(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reload_key(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn switch_server(&self, _player: &Player, _server_id: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn get_servers(&self) -> Vec<ServerConfig> {
+        self.servers.clone()
+    }
+
+    fn has_permission(&self, _sender: &dyn CommandSender, _permission: &str) -> bool {
+        self.permitted
+    }
+
+    fn shutdown(&self) {}
+}
+
+#[derive(Default)]
+pub(crate) struct TestSender {
+    pub(crate) messages: Mutex<Vec<String>>
+}
+
+impl CommandSender for TestSender {
+    fn send_message(&self, message: Chat) {
+        self.messages.lock().unwrap().push(message.to_plain());
+    }
+
+    fn get_name(&self) -> &str {
+        "Tester"
+    }
+}
+
+/// Two players online and two servers, granting every permission or none.
+pub(crate) fn server(permitted: bool) -> TestServer {
+    TestServer {
+        players: vec![Player::offline("Notch"), Player::offline("jeb_")],
+        servers: vec![
+            ServerConfig { id: "lobby", address: "127.0.0.1:25566", default: true },
+            ServerConfig { id: "hub", address: "127.0.0.1:25567", default: false }
+        ],
+        keys: Arc::new(KeyPair::generate().unwrap()),
+        permitted
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use crate::command::CommandSender;
+use crate::config::ServerConfig;
+use crate::packet::Chat;
+use crate::player::Player;
+use crate::server::Server;
+
+/// Runs a command once the words of it were matched, returning why it didn't work out if it didn't.
+pub type Executor = Box<dyn Fn(&Context) -> Result<(), String> + Send + Sync>;
+
+/// A command's syntax as a tree, in the spirit of Mojang's Brigadier. Every node is either a word
+/// that has to be typed as-is or an argument of some type, and the path through the tree that
+/// matches what was typed decides what runs.
+///
+/// ```ignore
+/// literal("server")
+///     .executes(|context| list(context))
+///     .then(argument("server", ArgumentType::Server)
+///         .executes(|context| switch(context, context.get_server("server"))))
+/// ```
+pub struct Node {
+    name: &'static str,
+    kind: NodeKind,
+    children: Vec<Node>,
+    executor: Option<Executor>,
+    permission: Option<&'static str>
+}
+
+enum NodeKind {
+    Literal,
+    Argument(ArgumentType)
+}
+
+/// What an argument node accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgumentType {
+    /// The name of an online player, ignoring case.
+    Player,
+    /// The id of a server in the config.
+    Server,
+    /// A whole number in `min..=max`.
+    Integer { min: i64, max: i64 },
+    /// Everything from here to the end of the command.
+    GreedyString
+}
+
+/// An argument as it was parsed.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Player(Player),
+    Server(ServerConfig),
+    Integer(i64),
+    String(String)
+}
+
+/// What a command's executor gets to work with: who ran it and the arguments by name.
+pub struct Context<'a> {
+    pub sender: &'a dyn CommandSender,
+    pub server: &'a (dyn Server + Send + Sync),
+    arguments: HashMap<&'static str, Value>
+}
+
+impl<'a> Context<'a> {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.arguments.get(name)
+    }
+
+    /// The player argument called `name`. Panics if the node path the executor sits on has no
+    /// such argument, which is a mistake in the tree rather than in what was typed.
+    pub fn get_player(&self, name: &str) -> &Player {
+        match self.get(name) {
+            Some(Value::Player(player)) => player,
+            _ => panic!("No player argument called {}", name)
+        }
+    }
+
+    pub fn get_server(&self, name: &str) -> &ServerConfig {
+        match self.get(name) {
+            Some(Value::Server(server)) => server,
+            _ => panic!("No server argument called {}", name)
+        }
+    }
+
+    pub fn get_integer(&self, name: &str) -> i64 {
+        match self.get(name) {
+            Some(Value::Integer(value)) => *value,
+            _ => panic!("No integer argument called {}", name)
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> &str {
+        match self.get(name) {
+            Some(Value::String(value)) => value,
+            _ => panic!("No string argument called {}", name)
+        }
+    }
+}
+
+/// A word that has to be typed as it is, ignoring case.
+pub fn literal(name: &'static str) -> Node {
+    Node::new(name, NodeKind::Literal)
+}
+
+/// An argument of `kind`, available to executors further down as `name`.
+pub fn argument(name: &'static str, kind: ArgumentType) -> Node {
+    Node::new(name, NodeKind::Argument(kind))
+}
+
+impl Node {
+    fn new(name: &'static str, kind: NodeKind) -> Self {
+        Node {
+            name,
+            kind,
+            children: Vec::new(),
+            executor: None,
+            permission: None
+        }
+    }
+
+    pub fn then(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Makes the command complete at this node.
+    pub fn executes<F>(mut self, executor: F) -> Self
+    where
+        F: Fn(&Context) -> Result<(), String> + Send + Sync + 'static
+    {
+        self.executor = Some(Box::new(executor));
+        self
+    }
+
+    /// Hides this node and everything under it from senders without `permission`.
+    pub fn requires(mut self, permission: &'static str) -> Self {
+        self.permission = Some(permission);
+        self
+    }
+
+    /// Runs the command this tree's root is the label of, with what was typed after the label.
+    pub fn execute(&self, sender: &dyn CommandSender, server: &(dyn Server + Send + Sync), arguments: Vec<String>) {
+        let mut context = Context {
+            sender,
+            server,
+            arguments: HashMap::new()
+        };
+
+        let mut path = vec![self.name.to_owned()];
+        if let Err(error) = self.run(&mut context, &arguments, &mut path) {
+            // what players typed ends up in the error, so none of it is read as markup
+            sender.send_message(Chat::text(error).color("red"));
+        }
+    }
+
+    /// Completions for the last of `arguments`, matched against this tree's root as the label.
+    pub fn suggest(&self, sender: &dyn CommandSender, server: &(dyn Server + Send + Sync), arguments: Vec<String>) -> Vec<String> {
+        let mut suggestions = Vec::new();
+        self.collect_suggestions(sender, server, &arguments, &mut suggestions);
+
+        suggestions.sort_by_key(|suggestion| suggestion.to_lowercase());
+        suggestions.dedup();
+        suggestions
+    }
+
+    fn run(&self, context: &mut Context, arguments: &[String], path: &mut Vec<String>) -> Result<(), String> {
+        let (token, rest) = match arguments.split_first() {
+            Some(split) => split,
+            None => return match &self.executor {
+                Some(executor) => executor(context),
+                None => Err(self.usage(context, path))
+            }
+        };
+
+        let mut error = None;
+        for child in self.visible_children(context.sender, context.server) {
+            match &child.kind {
+                NodeKind::Literal if child.name.eq_ignore_ascii_case(token) => {
+                    path.push(child.name.to_owned());
+                    return child.run(context, rest, path);
+                },
+                NodeKind::Literal => {},
+                NodeKind::Argument(kind) => {
+                    // greedy strings swallow everything that's left in one go
+                    let (text, rest) = match kind {
+                        ArgumentType::GreedyString => (arguments.join(" "), &[][..]),
+                        _ => (token.to_owned(), rest)
+                    };
+
+                    match kind.parse(&text, context.server) {
+                        Ok(value) => {
+                            context.arguments.insert(child.name, value);
+                            path.push(child.token());
+                            return child.run(context, rest, path);
+                        },
+                        Err(reason) => error = error.or(Some(reason))
+                    }
+                }
+            }
+        }
+
+        Err(match error {
+            Some(error) => error,
+            None => format!("Unknown argument \"{}\". {}", token, self.usage(context, path))
+        })
+    }
+
+    fn collect_suggestions(&self, sender: &dyn CommandSender, server: &(dyn Server + Send + Sync), arguments: &[String], suggestions: &mut Vec<String>) {
+        let (token, rest) = match arguments.split_first() {
+            Some(split) => split,
+            None => return
+        };
+
+        let partial = token.to_lowercase();
+        for child in self.visible_children(sender, server) {
+            match &child.kind {
+                NodeKind::Literal if rest.is_empty() => {
+                    if child.name.to_lowercase().starts_with(&partial) {
+                        suggestions.push(child.name.to_owned());
+                    }
+                },
+                NodeKind::Literal if child.name.eq_ignore_ascii_case(token) => child.collect_suggestions(sender, server, rest, suggestions),
+                NodeKind::Literal => {},
+                NodeKind::Argument(kind) if rest.is_empty() => {
+                    suggestions.extend(kind.suggestions(server).into_iter()
+                        .filter(|suggestion| suggestion.to_lowercase().starts_with(&partial)));
+                },
+                NodeKind::Argument(ArgumentType::GreedyString) => {},
+                NodeKind::Argument(kind) => {
+                    if kind.parse(token, server).is_ok() {
+                        child.collect_suggestions(sender, server, rest, suggestions);
+                    }
+                }
+            }
+        }
+    }
+
+    fn visible_children<'a>(&'a self, sender: &'a dyn CommandSender, server: &'a (dyn Server + Send + Sync)) -> impl Iterator<Item = &'a Node> {
+        self.children.iter()
+            .filter(move |child| child.permission.is_none_or(|permission| server.has_permission(sender, permission)))
+    }
+
+    /// How this node shows up in usage messages.
+    fn token(&self) -> String {
+        match self.kind {
+            NodeKind::Literal => self.name.to_owned(),
+            NodeKind::Argument(_) => format!("<{}>", self.name)
+        }
+    }
+
+    /// What could come next after `path`, e.g. `Usage: /proxy <list|version>`.
+    fn usage(&self, context: &Context, path: &[String]) -> String {
+        let next: Vec<String> = self.visible_children(context.sender, context.server)
+            .map(Node::token)
+            .collect();
+
+        match next.len() {
+            0 => format!("Usage: /{}", path.join(" ")),
+            1 => format!("Usage: /{} {}", path.join(" "), next[0]),
+            _ => format!("Usage: /{} ({})", path.join(" "), next.join("|"))
+        }
+    }
+}
+
+impl ArgumentType {
+    fn parse(&self, text: &str, server: &(dyn Server + Send + Sync)) -> Result<Value, String> {
+        match *self {
+            ArgumentType::Player => server.get_player(text)
+                .map(Value::Player)
+                .ok_or_else(|| format!("{} isn't online.", text)),
+            ArgumentType::Server => server.get_servers().into_iter()
+                .find(|server| server.id.eq_ignore_ascii_case(text))
+                .map(Value::Server)
+                .ok_or_else(|| format!("There is no server called {}.", text)),
+            ArgumentType::Integer { min, max } => match text.parse::<i64>() {
+                Ok(value) if value < min => Err(format!("{} is too small, it has to be at least {}.", value, min)),
+                Ok(value) if value > max => Err(format!("{} is too big, it can be at most {}.", value, max)),
+                Ok(value) => Ok(Value::Integer(value)),
+                Err(_) => Err(format!("\"{}\" isn't a whole number.", text))
+            },
+            ArgumentType::GreedyString => Ok(Value::String(text.to_owned()))
+        }
+    }
+
+    fn suggestions(&self, server: &(dyn Server + Send + Sync)) -> Vec<String> {
+        match self {
+            ArgumentType::Player => server.get_players().into_iter()
+                .map(|player| player.name)
+                .collect(),
+            ArgumentType::Server => server.get_servers().into_iter()
+                .map(|server| server.id.to_owned())
+                .collect(),
+            _ => Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::testing::{TestSender, server};
+
+    fn reply(context: &Context, text: String) -> Result<(), String> {
+        context.sender.send_message(Chat::text(text));
+        Ok(())
+    }
+
+    fn syntax() -> Node {
+        literal("test")
+            .then(literal("send")
+                .then(argument("player", ArgumentType::Player)
+                    .then(argument("server", ArgumentType::Server).executes(|context| {
+                        reply(context, format!("{} to {}", context.get_player("player").name, context.get_server("server").id))
+                    }))))
+            .then(literal("repeat")
+                .then(argument("times", ArgumentType::Integer { min: 1, max: 3 })
+                    .then(argument("text", ArgumentType::GreedyString).executes(|context| {
+                        reply(context, context.get_string("text").repeat(context.get_integer("times") as usize))
+                    }))))
+            .then(literal("secret").requires("test.secret").executes(|context| reply(context, String::from("psst"))))
+    }
+
+    fn run(permitted: bool, line: &str) -> Vec<String> {
+        let sender = TestSender::default();
+        let arguments = line.split(' ').filter(|word| !word.is_empty()).map(str::to_owned).collect();
+        syntax().execute(&sender, &server(permitted), arguments);
+        sender.messages.into_inner().unwrap()
+    }
+
+    fn suggest(permitted: bool, arguments: &[&str]) -> Vec<String> {
+        syntax().suggest(&TestSender::default(), &server(permitted), arguments.iter().map(|word| (*word).to_owned()).collect())
+    }
+
+    #[test]
+    fn runs_the_executor_the_arguments_lead_to() {
+        assert_eq!(run(false, "SEND notch Hub"), ["Notch to hub"]);
+        assert_eq!(run(false, "repeat 2 ab c"), ["ab cab c"]);
+    }
+
+    #[test]
+    fn explains_arguments_that_do_not_parse() {
+        assert_eq!(run(false, "send Herobrine hub"), ["Herobrine isn't online."]);
+        assert_eq!(run(false, "send Notch nether"), ["There is no server called nether."]);
+        assert_eq!(run(false, "send Notch &c<bold>nether"), ["There is no server called &c<bold>nether."]);
+        assert_eq!(run(false, "repeat 4 a"), ["4 is too big, it can be at most 3."]);
+        assert_eq!(run(false, "repeat 0 a"), ["0 is too small, it has to be at least 1."]);
+        assert_eq!(run(false, "repeat lots a"), ["\"lots\" isn't a whole number."]);
+    }
+
+    #[test]
+    fn shows_what_could_come_next() {
+        assert_eq!(run(false, ""), ["Usage: /test (send|repeat)"]);
+        assert_eq!(run(true, ""), ["Usage: /test (send|repeat|secret)"]);
+        assert_eq!(run(false, "send Notch"), ["Usage: /test send <player> <server>"]);
+        assert_eq!(run(false, "fly"), ["Unknown argument \"fly\". Usage: /test (send|repeat)"]);
+    }
+
+    #[test]
+    fn hides_nodes_without_permission() {
+        assert_eq!(run(false, "secret"), ["Unknown argument \"secret\". Usage: /test (send|repeat)"]);
+        assert_eq!(run(true, "secret"), ["psst"]);
+    }
+
+    #[test]
+    fn suggests_what_fits_the_last_argument() {
+        assert_eq!(suggest(false, &[""]), ["repeat", "send"]);
+        assert_eq!(suggest(true, &["s"]), ["secret", "send"]);
+        assert_eq!(suggest(false, &["send", ""]), ["jeb_", "Notch"]);
+        assert_eq!(suggest(false, &["send", "notch", "h"]), ["hub"]);
+        assert!(suggest(false, &["send", "Herobrine", ""]).is_empty());
+        assert!(suggest(false, &["repeat", "2", ""]).is_empty());
+    }
+}
@@ -1,4 +1,5 @@
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::Error as _;
 use std::default::Default;
 use std::fs;
 use std::path::Path;
@@ -6,6 +7,11 @@ use log::{info, trace, warn};
 use std::net::ToSocketAddrs;
 use rand::seq::SliceRandom;
 use std::io::{Error, ErrorKind};
+use std::collections::HashMap;
+use crate::player::Player;
+
+/// Grants a permission to every player.
+const EVERYONE: &str = "*";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServerConfig {
@@ -21,15 +27,168 @@ impl ServerConfig {
 }
 
 
+/// How backends learn who's really connecting to them.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Forwarding {
+    None,
+    /// BungeeCord's, packed into the handshake address.
+    Legacy,
+    /// Velocity's, answered through a signed login plugin message.
+    Modern
+}
+
+// `ip_forward` used to be a plain bool, which meant legacy forwarding when on
+impl<'de> Deserialize<'de> for Forwarding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Enabled(bool),
+            Mode(String)
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Enabled(true) => Ok(Forwarding::Legacy),
+            Raw::Enabled(false) => Ok(Forwarding::None),
+            Raw::Mode(mode) => match mode.to_lowercase().as_ref() {
+                "none" => Ok(Forwarding::None),
+                "legacy" | "bungeecord" => Ok(Forwarding::Legacy),
+                "modern" | "velocity" => Ok(Forwarding::Modern),
+                _ => Err(D::Error::custom(format!("Unknown ip_forward mode \"{}\".", mode)))
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ProxyConfig {
     pub bind: &'static str,
-    pub ip_forward: bool,
+    pub ip_forward: Forwarding,
+    /// Shared with the backends to sign `Forwarding::Modern` player info with.
+    #[serde(default)]
+    pub forwarding_secret: Option<&'static str>,
     pub online_mode: bool,
+    /// Where players are authenticated in online mode, anything implementing Mojang's session API.
+    #[serde(default = "default_session_server")]
+    pub session_server: &'static str,
+    /// Seconds to wait on the session server before giving up on a login.
+    #[serde(default = "default_session_timeout")]
+    pub session_timeout: u64,
+    /// Seconds to wait on a backend to let a player in before giving up on it.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Has the session server check that players connect from the same IP they joined from.
+    #[serde(default)]
+    pub prevent_proxy_connections: bool,
     pub max_players: i32,
+    /// What happens when a player logs in while they're already connected.
+    #[serde(default)]
+    pub duplicate_login: DuplicateLogin,
+    /// Packets at least this many bytes long are compressed, -1 disables compression.
+    #[serde(default = "default_compression_threshold")]
+    pub compression_threshold: i32,
     pub motd: &'static str,
     pub favicon: Option<&'static str>,
-    pub servers: Vec<ServerConfig>
+    /// Where the RSA key pair is kept, so it survives restarts and can be shared between proxies.
+    #[serde(default)]
+    pub key_file: Option<&'static str>,
+    /// Ids of the servers to move players to, in order, when the server they're on kicks them.
+    #[serde(default)]
+    pub fallback: Vec<&'static str>,
+    pub servers: Vec<ServerConfig>,
+    #[serde(default)]
+    pub status: StatusConfig,
+    /// Permissions, each granted to a list of player names or UUIDs, or `*` for everyone.
+    #[serde(default = "default_permissions")]
+    pub permissions: HashMap<&'static str, Vec<&'static str>>,
+    #[serde(default)]
+    pub messages: Messages
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateLogin {
+    /// The player already connected is kicked to make way for the new login, like vanilla does.
+    #[default]
+    KickOld,
+    /// The new login is turned away.
+    RejectNew
+}
+
+/// Where the server list entry's MOTD and player counts come from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusMode {
+    /// Our own `motd` and `max_players`, with the players connected to rift.
+    Local,
+    /// Whatever `StatusConfig::server` answers with.
+    Passthrough,
+    /// Our own MOTD, with the online and max counts of every backend added up.
+    Aggregate
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StatusConfig {
+    pub mode: StatusMode,
+    /// The backend `StatusMode::Passthrough` asks, a default server if unset.
+    pub server: Option<&'static str>,
+    /// Seconds a backend's answer is reused for before it's asked again.
+    pub cache_ttl: u64,
+    /// Seconds to wait on a backend before leaving it out.
+    pub timeout: u64
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        StatusConfig {
+            mode: StatusMode::Local,
+            server: None,
+            cache_ttl: 5,
+            timeout: 3
+        }
+    }
+}
+
+/// What players are told when rift turns them away.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Messages {
+    /// The client broke the login protocol.
+    pub invalid_login: &'static str,
+    /// The session server doesn't know about the player joining.
+    pub failed_to_verify: &'static str,
+    /// The session server couldn't be reached.
+    pub authentication_unavailable: &'static str,
+    /// There are already `max_players` online.
+    pub server_full: &'static str,
+    /// The player is already connected and `duplicate_login` is `reject_new`.
+    pub already_connected: &'static str,
+    /// The player logged in again somewhere else and `duplicate_login` is `kick_old`.
+    pub logged_in_elsewhere: &'static str,
+    /// The client is older than anything rift supports. `{version}` is replaced by the supported range.
+    pub outdated_client: &'static str,
+    /// The client is newer than anything rift supports. `{version}` is replaced by the supported range.
+    pub outdated_server: &'static str,
+    /// The proxy is being stopped.
+    pub proxy_closed: &'static str
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Messages {
+            invalid_login: "&cInvalid login, please try again.",
+            failed_to_verify: "Failed to verify username!",
+            authentication_unavailable: "&cAuthentication servers are down, please try again later.",
+            server_full: "&cThe server is full!",
+            already_connected: "&cYou are already connected to this proxy!",
+            logged_in_elsewhere: "&cYou logged in from another location.",
+            outdated_client: "&cOutdated client! Please use {version}.",
+            outdated_server: "&cOutdated server! I'm still on {version}.",
+            proxy_closed: "&cThe proxy is shutting down."
+        }
+    }
 }
 
 impl ProxyConfig {
@@ -51,40 +210,137 @@ impl ProxyConfig {
         }
     }
 
-    pub(crate) fn set_favicon(&mut self, b64: String) {
+    pub fn set_favicon(&mut self, b64: String) {
         self.favicon = Some(Box::leak(b64.into_boxed_str()));
     }
 
+    /// Whether `player` was granted `permission`, by name (ignoring case), UUID or to everyone.
+    pub(crate) fn has_permission(&self, player: &Player, permission: &str) -> bool {
+        let id = player.id.to_string();
+
+        self.permissions.get(permission)
+            .is_some_and(|granted| granted.iter().any(|entry| *entry == EVERYONE || entry.eq_ignore_ascii_case(&player.name) || entry.replace('-', "").eq_ignore_ascii_case(&id.replace('-', ""))))
+    }
+
+    pub(crate) fn get_server(&self, id: &str) -> Option<&ServerConfig> {
+        self.servers.iter().find(|server| server.id == id)
+    }
+
+    /// The fallback servers to try for a player that got kicked from the server with id `except`.
+    pub(crate) fn get_fallback_servers(&self, except: &str) -> Vec<ServerConfig> {
+        self.fallback.iter()
+            .filter(|id| **id != except)
+            .filter_map(|id| match self.get_server(id) {
+                Some(server) => Some(server.clone()),
+                None => {
+                    warn!("Fallback server {} isn't defined!", id);
+                    None
+                }
+            })
+            .collect()
+    }
+
     pub(crate) fn get_default_server(&self) -> std::io::Result<ServerConfig> {
         let default_servers: Vec<ServerConfig> = self.servers
             .iter()
             .filter(|server| server.default)
-            .map(|server| server.clone())
+            .cloned()
             .collect();
 
         let to_return = default_servers.choose(&mut rand::thread_rng()).cloned();
 
-        to_return.ok_or(Error::new(ErrorKind::Other, "Packet read timed out."))
+        to_return.ok_or_else(|| Error::new(ErrorKind::NotFound, "No default or fallback server is configured!"))
     }
 }
 
 impl Default for ProxyConfig {
     fn default() -> Self {
-        let mut servers = Vec::new();
-        servers.push(ServerConfig {
+        let servers = vec![ServerConfig {
             id: "lobby",
             address: "localhost:25565",
             default: true
-        });
+        }];
 
         ProxyConfig {
             bind: "0.0.0.0:25570",
-            ip_forward: true,
+            ip_forward: Forwarding::Legacy,
+            forwarding_secret: None,
             online_mode: true,
+            session_server: default_session_server(),
+            session_timeout: default_session_timeout(),
+            connect_timeout: default_connect_timeout(),
+            prevent_proxy_connections: false,
             max_players: 20,
+            duplicate_login: DuplicateLogin::default(),
+            compression_threshold: default_compression_threshold(),
             motd: "&3Enter the rift.",
             favicon: None,
-            servers: servers
+            key_file: None,
+            fallback: vec!["lobby"],
+            servers,
+            status: StatusConfig::default(),
+            permissions: default_permissions(),
+            messages: Messages::default()
         }
     }
 }
+
+/// Everyone gets to use `/proxy` and `/server`, the parts of them that need more are left out.
+fn default_permissions() -> HashMap<&'static str, Vec<&'static str>> {
+    let mut permissions = HashMap::new();
+    permissions.insert(crate::command::proxy::PERMISSION, vec![EVERYONE]);
+    permissions.insert(crate::command::server::PERMISSION, vec![EVERYONE]);
+    permissions
+}
+
+fn default_compression_threshold() -> i32 {
+    256
+}
+
+fn default_session_server() -> &'static str {
+    "https://sessionserver.mojang.com"
+}
+
+fn default_session_timeout() -> u64 {
+    10
+}
+
+fn default_connect_timeout() -> u64 {
+    5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grants_permissions_by_name_id_or_to_everyone() {
+        let mut config = ProxyConfig::default();
+        let notch = Player::offline("Notch");
+        let jeb = Player::offline("jeb_");
+        config.permissions.insert("test.name", vec!["notch"]);
+        config.permissions.insert("test.id", vec!["b50ad385829d3141a2167e7d7539ba7f"]);
+
+        assert!(config.has_permission(&notch, "test.name"));
+        assert!(!config.has_permission(&jeb, "test.name"));
+        assert!(config.has_permission(&notch, "test.id"));
+        assert!(!config.has_permission(&jeb, "test.id"));
+        assert!(config.has_permission(&jeb, crate::command::server::PERMISSION));
+        assert!(!config.has_permission(&jeb, "test.unknown"));
+    }
+
+    #[test]
+    fn lets_everyone_use_the_commands_by_default() {
+        let config: ProxyConfig = toml::from_str(r#"
+            bind = "0.0.0.0:25577"
+            ip_forward = false
+            online_mode = false
+            max_players = 20
+            motd = "rift"
+            servers = []
+        "#).unwrap();
+
+        assert_eq!(config.permissions.get(crate::command::proxy::PERMISSION), Some(&vec![EVERYONE]));
+        assert_eq!(config.permissions.get(crate::command::server::PERMISSION), Some(&vec![EVERYONE]));
+    }
+}
@@ -25,6 +25,12 @@ pub struct Engine<E, C> where E: CommandExecutor {
     commands: Vec<Box<dyn Command + Send + Sync>>
 }
 
+impl<E, C> Default for Engine<E, C> where E: CommandExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<E, C> Engine<E, C> where E: CommandExecutor {
     pub fn new() -> Self {
         Engine {
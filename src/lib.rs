@@ -0,0 +1,8 @@
+pub mod packet;
+pub mod server;
+pub mod command;
+pub mod player;
+pub mod engine;
+pub mod config;
+pub mod protocol;
+pub mod util;
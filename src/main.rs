@@ -1,23 +1,14 @@
-mod packet;
-mod server;
-mod command;
-mod player;
-mod engine;
-mod config;
-mod protocol;
-mod util;
-
 use std::io;
 
-use crate::server::ProxyServer;
-use pretty_env_logger;
+use rift::server::ProxyServer;
 use log::{info};
-use crate::engine::{Engine};
-use crate::command::proxy::{ProxyCommand};
-use crate::config::ProxyConfig;
+use rift::engine::{Engine};
+use rift::command::proxy::{ProxyCommand};
+use rift::command::server::ServerCommand;
+use rift::config::ProxyConfig;
 use std::path::Path;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
@@ -40,6 +31,7 @@ async fn main() -> io::Result<()> {
         
         Engine::new()
           .command(ProxyCommand::default())
+          .command(ServerCommand::default())
           .config(cloned)
    })
     .bind(bind)
@@ -0,0 +1,676 @@
+//! Minecraft's JSON text components, the format of everything from chat messages to kick reasons.
+//!
+//! Components read from the wire are written back the way they came, fields rift doesn't know
+//! about included, short of plain strings and arrays being written as the objects they stand for.
+
+use std::fmt;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Number, Value};
+use crate::packet::version;
+use crate::util::color;
+
+/// A text component: what it shows, how it looks and the components that follow it, which
+/// inherit its style.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Raw", into = "Component")]
+pub struct Chat {
+    pub content: Content,
+    pub style: Style,
+    pub extra: Vec<Chat>,
+    /// Fields rift doesn't know about, kept so they survive being passed along.
+    pub other: Map<String, Value>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Content {
+    Text(String),
+    /// A key from the client's language file, with the components filling in its placeholders.
+    Translate {
+        key: String,
+        with: Vec<Chat>
+    },
+    /// A player's score in an objective.
+    Score(Score),
+    /// The names of the entities an entity selector picks.
+    Selector(String),
+    /// Whatever the client has bound to a control, e.g. `key.jump`.
+    Keybind(String),
+    /// NBT read from a block, entity or storage.
+    Nbt(Nbt)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    pub name: String,
+    pub objective: String,
+    /// Shown instead of the real score, when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Nbt {
+    pub nbt: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpret: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage: Option<String>
+}
+
+/// How a component looks, anything left unset is inherited from its parent.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Style {
+    /// One of the sixteen color names, `reset`, or `#RRGGBB` from 1.16 on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bold: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub italic: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underlined: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strikethrough: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<String>,
+    /// Put into the chat box when the component is shift-clicked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insertion: Option<String>,
+    #[serde(rename = "clickEvent", skip_serializing_if = "Option::is_none")]
+    pub click_event: Option<ClickEvent>,
+    #[serde(rename = "hoverEvent", skip_serializing_if = "Option::is_none")]
+    pub hover_event: Option<HoverEvent>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClickEvent {
+    pub action: ClickAction,
+    pub value: String
+}
+
+/// What clicking a component does. Actions rift doesn't know are kept as they were sent, so newer
+/// backends' components still make it through.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ClickAction {
+    OpenUrl,
+    OpenFile,
+    RunCommand,
+    SuggestCommand,
+    ChangePage,
+    CopyToClipboard,
+    TwitchUserInfo,
+    Other(String)
+}
+
+/// What's shown when hovering over a component. 1.16 moved the shown thing from `value` to
+/// `contents`, whichever the component came with is kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoverEvent {
+    pub action: HoverAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contents: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>
+}
+
+/// What's shown when hovering, unknown actions are kept like `ClickAction`'s.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum HoverAction {
+    ShowText,
+    ShowItem,
+    ShowEntity,
+    ShowAchievement,
+    Other(String)
+}
+
+impl Chat {
+    /// Text with markup tags and `&` color codes, see `util::color::markup`.
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        color::markup(&text.into())
+    }
+
+    pub fn text<S: Into<String>>(text: S) -> Self {
+        Chat::from_content(Content::Text(text.into()))
+    }
+
+    pub fn translate<S: Into<String>>(key: S) -> Self {
+        Chat::from_content(Content::Translate {
+            key: key.into(),
+            with: Vec::new()
+        })
+    }
+
+    pub fn score<S: Into<String>>(name: S, objective: S) -> Self {
+        Chat::from_content(Content::Score(Score {
+            name: name.into(),
+            objective: objective.into(),
+            value: None
+        }))
+    }
+
+    pub fn selector<S: Into<String>>(selector: S) -> Self {
+        Chat::from_content(Content::Selector(selector.into()))
+    }
+
+    pub fn keybind<S: Into<String>>(key: S) -> Self {
+        Chat::from_content(Content::Keybind(key.into()))
+    }
+
+    fn from_content(content: Content) -> Self {
+        Chat {
+            content,
+            style: Style::default(),
+            extra: Vec::new(),
+            other: Map::new()
+        }
+    }
+
+    /// Adds a placeholder argument to a translated component, other components are left as is.
+    pub fn with(mut self, argument: Chat) -> Self {
+        if let Content::Translate { with, .. } = &mut self.content {
+            with.push(argument);
+        }
+
+        self
+    }
+
+    pub fn color<S: Into<String>>(mut self, color: S) -> Self {
+        self.style.color = Some(color.into());
+        self
+    }
+
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.style.bold = Some(bold);
+        self
+    }
+
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.style.italic = Some(italic);
+        self
+    }
+
+    pub fn underlined(mut self, underlined: bool) -> Self {
+        self.style.underlined = Some(underlined);
+        self
+    }
+
+    pub fn strikethrough(mut self, strikethrough: bool) -> Self {
+        self.style.strikethrough = Some(strikethrough);
+        self
+    }
+
+    pub fn obfuscated(mut self, obfuscated: bool) -> Self {
+        self.style.obfuscated = Some(obfuscated);
+        self
+    }
+
+    pub fn font<S: Into<String>>(mut self, font: S) -> Self {
+        self.style.font = Some(font.into());
+        self
+    }
+
+    pub fn insertion<S: Into<String>>(mut self, insertion: S) -> Self {
+        self.style.insertion = Some(insertion.into());
+        self
+    }
+
+    pub fn click(mut self, event: ClickEvent) -> Self {
+        self.style.click_event = Some(event);
+        self
+    }
+
+    pub fn hover(mut self, event: HoverEvent) -> Self {
+        self.style.hover_event = Some(event);
+        self
+    }
+
+    /// Adds a component after this one's content.
+    pub fn append(mut self, child: Chat) -> Self {
+        self.extra.push(child);
+        self
+    }
+
+    /// The translation key, if this is a translated component.
+    pub fn translation_key(&self) -> Option<&str> {
+        match &self.content {
+            Content::Translate { key, .. } => Some(key),
+            _ => None
+        }
+    }
+
+    /// The text the component shows without any styling, as far as that can be known without the
+    /// client. Translations show their key, followed by their arguments.
+    pub fn to_plain(&self) -> String {
+        let mut plain = String::new();
+        self.write_plain(&mut plain);
+        plain
+    }
+
+    fn write_plain(&self, plain: &mut String) {
+        plain.push_str(&self.content_plain());
+
+        for child in &self.extra {
+            child.write_plain(plain);
+        }
+    }
+
+    /// `to_plain` for just this component's content, leaving out what follows it.
+    pub(crate) fn content_plain(&self) -> String {
+        match &self.content {
+            Content::Text(text) => text.to_owned(),
+            Content::Translate { key, with } => {
+                let mut plain = key.to_owned();
+                for argument in with {
+                    plain.push(' ');
+                    argument.write_plain(&mut plain);
+                }
+                plain
+            },
+            Content::Score(score) => score.value.to_owned().unwrap_or_else(|| score.name.to_owned()),
+            Content::Selector(selector) => selector.to_owned(),
+            Content::Keybind(key) => key.to_owned(),
+            Content::Nbt(nbt) => nbt.nbt.to_owned()
+        }
+    }
+
+    /// The component as a client on protocol `version` can show it: clients before 1.16 don't know
+    /// hex colors, so they get the closest legacy color instead.
+    pub fn for_version(&self, version: i32) -> Chat {
+        let mut chat = self.clone();
+        if version < version::V1_16 {
+            chat.downsample();
+        }
+
+        chat
+    }
+
+    fn downsample(&mut self) {
+        if let Some(color) = &self.style.color {
+            self.style.color = Some(color::downsample(color));
+        }
+
+        if let Content::Translate { with, .. } = &mut self.content {
+            with.iter_mut().for_each(Chat::downsample);
+        }
+
+        self.extra.iter_mut().for_each(Chat::downsample);
+    }
+
+    /// The component as text with `§` codes, for the places that predate JSON chat.
+    pub fn to_legacy(&self) -> String {
+        color::to_legacy(self)
+    }
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Chat::text("")
+    }
+}
+
+impl fmt::Display for Chat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_plain())
+    }
+}
+
+impl ClickEvent {
+    pub fn new<S: Into<String>>(action: ClickAction, value: S) -> Self {
+        ClickEvent {
+            action,
+            value: value.into()
+        }
+    }
+
+    pub fn open_url<S: Into<String>>(url: S) -> Self {
+        ClickEvent::new(ClickAction::OpenUrl, url)
+    }
+
+    pub fn run_command<S: Into<String>>(command: S) -> Self {
+        ClickEvent::new(ClickAction::RunCommand, command)
+    }
+
+    pub fn suggest_command<S: Into<String>>(command: S) -> Self {
+        ClickEvent::new(ClickAction::SuggestCommand, command)
+    }
+
+    pub fn copy_to_clipboard<S: Into<String>>(text: S) -> Self {
+        ClickEvent::new(ClickAction::CopyToClipboard, text)
+    }
+}
+
+impl HoverEvent {
+    /// Shows `text`, written both ways so clients before and after 1.16 understand it.
+    pub fn show_text(text: Chat) -> Self {
+        let text = serde_json::to_value(text).unwrap_or_default();
+
+        HoverEvent {
+            action: HoverAction::ShowText,
+            contents: Some(text.clone()),
+            value: Some(text)
+        }
+    }
+}
+
+impl From<String> for ClickAction {
+    fn from(action: String) -> Self {
+        match action.as_str() {
+            "open_url" => ClickAction::OpenUrl,
+            "open_file" => ClickAction::OpenFile,
+            "run_command" => ClickAction::RunCommand,
+            "suggest_command" => ClickAction::SuggestCommand,
+            "change_page" => ClickAction::ChangePage,
+            "copy_to_clipboard" => ClickAction::CopyToClipboard,
+            "twitch_user_info" => ClickAction::TwitchUserInfo,
+            _ => ClickAction::Other(action)
+        }
+    }
+}
+
+impl From<ClickAction> for String {
+    fn from(action: ClickAction) -> Self {
+        match action {
+            ClickAction::OpenUrl => "open_url".to_owned(),
+            ClickAction::OpenFile => "open_file".to_owned(),
+            ClickAction::RunCommand => "run_command".to_owned(),
+            ClickAction::SuggestCommand => "suggest_command".to_owned(),
+            ClickAction::ChangePage => "change_page".to_owned(),
+            ClickAction::CopyToClipboard => "copy_to_clipboard".to_owned(),
+            ClickAction::TwitchUserInfo => "twitch_user_info".to_owned(),
+            ClickAction::Other(action) => action
+        }
+    }
+}
+
+impl From<String> for HoverAction {
+    fn from(action: String) -> Self {
+        match action.as_str() {
+            "show_text" => HoverAction::ShowText,
+            "show_item" => HoverAction::ShowItem,
+            "show_entity" => HoverAction::ShowEntity,
+            "show_achievement" => HoverAction::ShowAchievement,
+            _ => HoverAction::Other(action)
+        }
+    }
+}
+
+impl From<HoverAction> for String {
+    fn from(action: HoverAction) -> Self {
+        match action {
+            HoverAction::ShowText => "show_text".to_owned(),
+            HoverAction::ShowItem => "show_item".to_owned(),
+            HoverAction::ShowEntity => "show_entity".to_owned(),
+            HoverAction::ShowAchievement => "show_achievement".to_owned(),
+            HoverAction::Other(action) => action
+        }
+    }
+}
+
+/// Everything a component can be sent as: a bare string, an array that's the first component
+/// followed by the rest or a full object.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Raw {
+    Text(String),
+    Number(Number),
+    Bool(bool),
+    Array(Vec<Chat>),
+    Component(Box<Component>)
+}
+
+/// A component the way it's written out.
+#[derive(Default, Serialize, Deserialize)]
+struct Component {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translate: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    with: Vec<Chat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<Score>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    selector: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keybind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nbt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interpret: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage: Option<String>,
+    #[serde(flatten)]
+    style: Style,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extra: Vec<Chat>,
+    #[serde(flatten)]
+    other: Map<String, Value>
+}
+
+impl From<Raw> for Chat {
+    fn from(raw: Raw) -> Self {
+        match raw {
+            Raw::Text(text) => Chat::text(text),
+            Raw::Number(number) => Chat::text(number.to_string()),
+            Raw::Bool(boolean) => Chat::text(boolean.to_string()),
+            Raw::Array(components) => {
+                let mut components = components.into_iter();
+                let mut first = components.next().unwrap_or_default();
+                first.extra.extend(components);
+                first
+            },
+            Raw::Component(component) => Chat::from(*component)
+        }
+    }
+}
+
+impl From<Component> for Chat {
+    fn from(component: Component) -> Self {
+        // which of these wins when there's more than one is up to the client, this is its order
+        let content = if let Some(text) = component.text {
+            Content::Text(text)
+        } else if let Some(key) = component.translate {
+            Content::Translate {
+                key,
+                with: component.with
+            }
+        } else if let Some(score) = component.score {
+            Content::Score(score)
+        } else if let Some(selector) = component.selector {
+            Content::Selector(selector)
+        } else if let Some(key) = component.keybind {
+            Content::Keybind(key)
+        } else if let Some(nbt) = component.nbt {
+            Content::Nbt(Nbt {
+                nbt,
+                interpret: component.interpret,
+                block: component.block,
+                entity: component.entity,
+                storage: component.storage
+            })
+        } else {
+            Content::Text(String::new())
+        };
+
+        Chat {
+            content,
+            style: component.style,
+            extra: component.extra,
+            other: component.other
+        }
+    }
+}
+
+impl From<Chat> for Component {
+    fn from(chat: Chat) -> Self {
+        let mut component = Component {
+            style: chat.style,
+            extra: chat.extra,
+            other: chat.other,
+            ..Component::default()
+        };
+
+        match chat.content {
+            Content::Text(text) => component.text = Some(text),
+            Content::Translate { key, with } => {
+                component.translate = Some(key);
+                component.with = with;
+            },
+            Content::Score(score) => component.score = Some(score),
+            Content::Selector(selector) => component.selector = Some(selector),
+            Content::Keybind(key) => component.keybind = Some(key),
+            Content::Nbt(nbt) => {
+                component.nbt = Some(nbt.nbt);
+                component.interpret = nbt.interpret;
+                component.block = nbt.block;
+                component.entity = nbt.entity;
+                component.storage = nbt.storage;
+            }
+        }
+
+        component
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(json: Value) -> Value {
+        let chat: Chat = serde_json::from_value(json).unwrap();
+        serde_json::to_value(chat).unwrap()
+    }
+
+    #[test]
+    fn writes_components_back_the_way_they_came() {
+        let component = json!({
+            "translate": "chat.type.text",
+            "with": [{"text": "Notch", "color": "#ff8800"}, {"keybind": "key.jump"}],
+            "bold": true,
+            "clickEvent": {"action": "suggest_command", "value": "/msg Notch "},
+            "hoverEvent": {"action": "show_entity", "contents": {"type": "minecraft:player", "name": "Notch"}},
+            "extra": [{"score": {"name": "Notch", "objective": "kills"}}],
+            "futureField": [1, 2, 3]
+        });
+
+        assert_eq!(round_trip(component.clone()), component);
+    }
+
+    #[test]
+    fn keeps_actions_it_does_not_know() {
+        let component = json!({
+            "text": "",
+            "clickEvent": {"action": "open_dialog", "value": "welcome"},
+            "hoverEvent": {"action": "show_dialog", "value": "welcome"}
+        });
+
+        let chat: Chat = serde_json::from_value(component.clone()).unwrap();
+        assert_eq!(chat.style.click_event.as_ref().unwrap().action, ClickAction::Other("open_dialog".to_owned()));
+        assert_eq!(serde_json::to_value(chat).unwrap(), component);
+    }
+
+    #[test]
+    fn reads_strings_and_arrays_as_components() {
+        assert_eq!(round_trip(json!("Hello")), json!({"text": "Hello"}));
+        assert_eq!(round_trip(json!(["Hello", {"text": " world", "italic": true}])),
+            json!({"text": "Hello", "extra": [{"text": " world", "italic": true}]}));
+    }
+
+    #[test]
+    fn builds_every_kind_of_content() {
+        assert_eq!(serde_json::to_value(Chat::text("Hello")).unwrap(), json!({"text": "Hello"}));
+        assert_eq!(serde_json::to_value(Chat::translate("chat.type.text").with(Chat::text("Notch")).with(Chat::text("Hi"))).unwrap(),
+            json!({"translate": "chat.type.text", "with": [{"text": "Notch"}, {"text": "Hi"}]}));
+        assert_eq!(serde_json::to_value(Chat::score("Notch", "kills")).unwrap(),
+            json!({"score": {"name": "Notch", "objective": "kills"}}));
+        assert_eq!(serde_json::to_value(Chat::selector("@a[distance=..5]")).unwrap(), json!({"selector": "@a[distance=..5]"}));
+        assert_eq!(serde_json::to_value(Chat::keybind("key.jump")).unwrap(), json!({"keybind": "key.jump"}));
+    }
+
+    #[test]
+    fn leaves_with_off_components_that_are_not_translated() {
+        assert_eq!(Chat::text("Hello").with(Chat::text("Notch")), Chat::text("Hello"));
+    }
+
+    #[test]
+    fn builds_every_style() {
+        let chat = Chat::text("Hello")
+            .color("gold")
+            .bold(true)
+            .italic(false)
+            .underlined(true)
+            .strikethrough(false)
+            .obfuscated(true)
+            .font("minecraft:uniform")
+            .insertion("Hello")
+            .click(ClickEvent::run_command("/server lobby"))
+            .hover(HoverEvent::show_text(Chat::text("Click!")))
+            .append(Chat::text(" world"));
+
+        assert_eq!(serde_json::to_value(chat).unwrap(), json!({
+            "text": "Hello",
+            "color": "gold",
+            "bold": true,
+            "italic": false,
+            "underlined": true,
+            "strikethrough": false,
+            "obfuscated": true,
+            "font": "minecraft:uniform",
+            "insertion": "Hello",
+            "clickEvent": {"action": "run_command", "value": "/server lobby"},
+            "hoverEvent": {"action": "show_text", "contents": {"text": "Click!"}, "value": {"text": "Click!"}},
+            "extra": [{"text": " world"}]
+        }));
+    }
+
+    #[test]
+    fn builds_click_events_with_their_actions() {
+        let events = [
+            (ClickEvent::open_url("https://example.com"), json!({"action": "open_url", "value": "https://example.com"})),
+            (ClickEvent::run_command("/proxy"), json!({"action": "run_command", "value": "/proxy"})),
+            (ClickEvent::suggest_command("/server "), json!({"action": "suggest_command", "value": "/server "})),
+            (ClickEvent::copy_to_clipboard("Notch"), json!({"action": "copy_to_clipboard", "value": "Notch"})),
+            (ClickEvent::new(ClickAction::ChangePage, "2"), json!({"action": "change_page", "value": "2"}))
+        ];
+
+        for (event, expected) in events.iter() {
+            assert_eq!(&serde_json::to_value(Chat::text("").click(event.clone())).unwrap()["clickEvent"], expected);
+        }
+    }
+
+    #[test]
+    fn downsamples_hex_colors_for_clients_before_1_16() {
+        let chat = Chat::text("Hello").color("#ff5555")
+            .append(Chat::text(" world").color("#0000aa"))
+            .append(Chat::translate("chat.type.text").with(Chat::text("Notch").color("#55ff55")));
+
+        let downsampled = chat.for_version(version::V1_16 - 1);
+        assert_eq!(downsampled.style.color.as_deref(), Some("red"));
+        assert_eq!(downsampled.extra[0].style.color.as_deref(), Some("dark_blue"));
+        match &downsampled.extra[1].content {
+            Content::Translate { with, .. } => assert_eq!(with[0].style.color.as_deref(), Some("green")),
+            _ => unreachable!()
+        }
+
+        assert_eq!(chat.for_version(version::V1_16), chat);
+    }
+
+    #[test]
+    fn leaves_named_colors_alone() {
+        let chat = Chat::text("Hello").color("gold");
+        assert_eq!(chat.for_version(version::V1_8), chat);
+    }
+}
@@ -0,0 +1,330 @@
+use std::io;
+use std::io::{Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use crate::packet::frame::{MAX_FRAME_SIZE, read_varint, write_varint};
+
+// vanilla refuses to inflate anything bigger than this
+const MAX_DATA_LENGTH: usize = 8388608;
+const READ_SIZE: usize = 8192;
+
+/// Translates between the compressed frame format (`length | data length | zlib body`) used once
+/// Set Compression has been sent and the plain `length | id | payload` frames everything else
+/// in `packet` reads and writes.
+///
+/// Compression starts out disabled, in which case bytes pass straight through. Call
+/// [`set_threshold`](CompressedStream::set_threshold) right after Set Compression goes over the wire.
+pub struct CompressedStream<S> {
+    inner: S,
+    threshold: i32,
+    // compressed bytes read from `inner` that don't make up a whole frame yet
+    inbound: Vec<u8>,
+    // plain frames ready to be handed out
+    decoded: Vec<u8>,
+    read: usize,
+    // plain bytes written to us that don't make up a whole frame yet
+    outbound: Vec<u8>,
+    // compressed frames `inner` hasn't accepted yet
+    encoded: Vec<u8>,
+    written: usize
+}
+
+impl<S> CompressedStream<S> {
+    pub fn new(inner: S) -> Self {
+        CompressedStream {
+            inner,
+            threshold: -1,
+            inbound: Vec::new(),
+            decoded: Vec::new(),
+            read: 0,
+            outbound: Vec::new(),
+            encoded: Vec::new(),
+            written: 0
+        }
+    }
+
+    /// Packets at least `threshold` bytes long get compressed, a negative threshold disables compression.
+    pub fn set_threshold(&mut self, threshold: i32) {
+        self.threshold = threshold;
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.threshold >= 0
+    }
+
+    fn decode_frames(&mut self) -> io::Result<()> {
+        while let Some((length, header)) = read_varint(&self.inbound)? {
+            if length < 0 || length as usize > MAX_FRAME_SIZE {
+                return Err(invalid("Packet is too big."));
+            }
+
+            let end = header + length as usize;
+            if self.inbound.len() < end {
+                break;
+            }
+
+            let frame = &self.inbound[header..end];
+            let (data_length, data_header) = read_varint(frame)?
+                .ok_or_else(|| invalid("Compressed packet is missing its data length."))?;
+
+            let data = if data_length == 0 {
+                frame[data_header..].to_vec()
+            } else {
+                if data_length < 0 || data_length as usize > MAX_DATA_LENGTH {
+                    return Err(invalid("Compressed packet is too big."));
+                }
+
+                let mut data = Vec::with_capacity(data_length as usize);
+                ZlibDecoder::new(&frame[data_header..])
+                    .take(data_length as u64 + 1)
+                    .read_to_end(&mut data)?;
+
+                if data.len() != data_length as usize {
+                    return Err(invalid("Compressed packet doesn't match its data length."));
+                }
+
+                data
+            };
+
+            write_varint(&mut self.decoded, data.len() as i32);
+            self.decoded.extend_from_slice(&data);
+            self.inbound.drain(..end);
+        }
+
+        Ok(())
+    }
+
+    fn encode_frames(&mut self) -> io::Result<()> {
+        while let Some((length, header)) = read_varint(&self.outbound)? {
+            if length < 0 {
+                return Err(invalid("Packet length is negative."));
+            }
+
+            let end = header + length as usize;
+            if self.outbound.len() < end {
+                break;
+            }
+
+            let data = &self.outbound[header..end];
+            let mut body = Vec::new();
+
+            if data.len() >= self.threshold as usize {
+                write_varint(&mut body, data.len() as i32);
+
+                let mut encoder = ZlibEncoder::new(body, Compression::default());
+                encoder.write_all(data)?;
+                body = encoder.finish()?;
+            } else {
+                write_varint(&mut body, 0);
+                body.extend_from_slice(data);
+            }
+
+            write_varint(&mut self.encoded, body.len() as i32);
+            self.encoded.extend_from_slice(&body);
+            self.outbound.drain(..end);
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> CompressedStream<S> {
+    fn poll_write_encoded(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.encoded.len() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.encoded[self.written..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::WriteZero, "Failed to write compressed data.")));
+                },
+                Poll::Ready(Ok(written)) => self.written += written,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+
+        self.encoded.clear();
+        self.written = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CompressedStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.read < this.decoded.len() {
+                let available = &this.decoded[this.read..];
+                let length = available.len().min(buf.len());
+
+                buf[..length].copy_from_slice(&available[..length]);
+                this.read += length;
+
+                if this.read == this.decoded.len() {
+                    this.decoded.clear();
+                    this.read = 0;
+                }
+
+                return Poll::Ready(Ok(length));
+            }
+
+            if !this.is_enabled() && this.inbound.is_empty() {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+
+            let mut chunk = [0; READ_SIZE];
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(0)) => {
+                    if this.inbound.is_empty() {
+                        return Poll::Ready(Ok(0));
+                    }
+
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed mid packet.")));
+                },
+                Poll::Ready(Ok(read)) => {
+                    this.inbound.extend_from_slice(&chunk[..read]);
+                    if let Err(error) = this.decode_frames() {
+                        return Poll::Ready(Err(error));
+                    }
+                },
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CompressedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match this.poll_write_encoded(cx) {
+            Poll::Ready(Ok(())) => {},
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending
+        }
+
+        if !this.is_enabled() && this.outbound.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        this.outbound.extend_from_slice(buf);
+        if let Err(error) = this.encode_frames() {
+            return Poll::Ready(Err(error));
+        }
+
+        // whatever doesn't make it out now goes out on the next write or flush
+        if let Poll::Ready(Err(error)) = this.poll_write_encoded(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_write_encoded(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_write_encoded(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn frame(id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_varint(&mut frame, payload.len() as i32 + 1);
+        frame.push(id);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    async fn round_trip(threshold: i32, frames: &[u8]) -> Vec<u8> {
+        let mut writer = CompressedStream::new(Vec::new());
+        writer.set_threshold(threshold);
+        writer.write_all(frames).await.unwrap();
+        writer.flush().await.unwrap();
+
+        let mut reader = CompressedStream::new(writer.inner.as_slice());
+        reader.set_threshold(threshold);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        read
+    }
+
+    #[tokio::test]
+    async fn round_trips_above_and_below_the_threshold() {
+        let mut frames = frame(0x01, b"small");
+        frames.extend(frame(0x02, &[7; 1000]));
+
+        assert_eq!(round_trip(256, &frames).await, frames);
+        assert_eq!(round_trip(0, &frames).await, frames);
+        assert_eq!(round_trip(-1, &frames).await, frames);
+    }
+
+    #[tokio::test]
+    async fn compresses_only_at_the_threshold() {
+        let mut writer = CompressedStream::new(Vec::new());
+        writer.set_threshold(256);
+        writer.write_all(&frame(0x01, &[0; 1000])).await.unwrap();
+
+        assert!(writer.inner.len() < 100);
+    }
+
+    fn compressed(data_length: i32, data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        write_varint(&mut body, data_length);
+
+        let mut encoder = ZlibEncoder::new(body, Compression::default());
+        encoder.write_all(data).unwrap();
+        let body = encoder.finish().unwrap();
+
+        let mut frame = Vec::new();
+        write_varint(&mut frame, body.len() as i32);
+        frame.extend(body);
+        frame
+    }
+
+    async fn read_compressed(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut reader = CompressedStream::new(bytes);
+        reader.set_threshold(256);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await?;
+        Ok(read)
+    }
+
+    #[tokio::test]
+    async fn refuses_to_inflate_more_than_it_was_told() {
+        // a few KB of zeros claiming to be 300 bytes, it would have to inflate past what was claimed
+        let error = read_compressed(&compressed(300, &[0; 4096])).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn refuses_data_lengths_past_the_limit() {
+        let error = read_compressed(&compressed(MAX_DATA_LENGTH as i32 + 1, &[0; 16])).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
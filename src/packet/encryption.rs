@@ -0,0 +1,186 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+use aes::Aes128;
+use cfb8::Cfb8;
+use cfb8::stream_cipher::{NewStreamCipher, StreamCipher};
+
+type AesCfb8 = Cfb8<Aes128>;
+
+/// Wraps a connection that has completed the encryption handshake.
+///
+/// Minecraft encrypts each direction as one continuous AES/CFB8 stream keyed (and iv'd) with the
+/// shared secret, so both cipher states are kept for the lifetime of the connection rather than
+/// per packet.
+pub struct EncryptedStream<S> {
+    inner: S,
+    encryptor: AesCfb8,
+    decryptor: AesCfb8,
+    // ciphertext the inner stream hasn't accepted yet, the encryptor has already moved past it
+    pending: Vec<u8>,
+    written: usize
+}
+
+impl<S> EncryptedStream<S> {
+    pub fn new(inner: S, secret: &[u8]) -> Self {
+        EncryptedStream {
+            inner,
+            encryptor: AesCfb8::new_var(secret, secret).unwrap(),
+            decryptor: AesCfb8::new_var(secret, secret).unwrap(),
+            pending: Vec::new(),
+            written: 0
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> EncryptedStream<S> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(io::Error::new(io::ErrorKind::WriteZero, "Failed to write encrypted data.")));
+                },
+                Poll::Ready(Ok(written)) => self.written += written,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending
+            }
+        }
+
+        self.pending.clear();
+        self.written = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for EncryptedStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(read)) => {
+                this.decryptor.decrypt(&mut buf[..read]);
+                Poll::Ready(Ok(read))
+            },
+            other => other
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for EncryptedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => {},
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending
+        }
+
+        this.pending.extend_from_slice(buf);
+        this.encryptor.encrypt(&mut this.pending);
+
+        // whatever doesn't make it out now goes out on the next write or flush
+        if let Poll::Ready(Err(error)) = this.poll_write_pending(cx) {
+            return Poll::Ready(Err(error));
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_write_pending(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const SECRET: &[u8] = &[0x2a; 16];
+
+    /// A connection that takes at most a few bytes per write and refuses every other one.
+    struct Trickle {
+        written: Vec<u8>,
+        ready: bool
+    }
+
+    impl AsyncWrite for Trickle {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+
+            if !this.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let written = buf.len().min(3);
+            this.written.extend_from_slice(&buf[..written]);
+            Poll::Ready(Ok(written))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// A connection that hands back at most a few bytes per read.
+    struct Dribble<'a>(&'a [u8]);
+
+    impl AsyncRead for Dribble<'_> {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            let read = buf.len().min(this.0.len()).min(5);
+            buf[..read].copy_from_slice(&this.0[..read]);
+            this.0 = &this.0[read..];
+            Poll::Ready(Ok(read))
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trips_packets_across_split_and_partial_writes() {
+        let packets: Vec<Vec<u8>> = vec![b"\x06\x00hello".to_vec(), vec![0x7f; 40], b"\x03\x01bye".to_vec()];
+
+        let mut writer = EncryptedStream::new(Trickle { written: Vec::new(), ready: false }, SECRET);
+        for packet in &packets {
+            let (head, tail) = packet.split_at(packet.len() / 2);
+            writer.write_all(head).await.unwrap();
+            writer.write_all(tail).await.unwrap();
+        }
+        writer.flush().await.unwrap();
+
+        let mut expected = packets.concat();
+        AesCfb8::new_var(SECRET, SECRET).unwrap().encrypt(&mut expected);
+
+        // one continuous cipher over everything, not a fresh one per write
+        assert_eq!(writer.inner.written, expected);
+
+        let mut reader = EncryptedStream::new(Dribble(&writer.inner.written), SECRET);
+        for packet in &packets {
+            let mut read = vec![0; packet.len()];
+            reader.read_exact(&mut read).await.unwrap();
+            assert_eq!(&read, packet);
+        }
+    }
+}
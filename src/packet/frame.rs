@@ -0,0 +1,219 @@
+use std::io::{Cursor, Error, ErrorKind, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use crate::packet::{Packet, In, Out, AsyncPacketReadExt, AsyncPacketWriteExt};
+use crate::packet::registry::{self, Kind};
+
+/// The biggest frame a client or server is allowed to send, anything longer than a 3 byte VarInt
+/// can describe is rejected by vanilla as well.
+pub const MAX_FRAME_SIZE: usize = 2097151;
+
+/// A packet whose id is known but whose payload is left undecoded. Play traffic is relayed as
+/// raw packets, only the ones something registered an interest in ever get decoded.
+///
+/// Packets are decoded from the payload alone, so a packet that's malformed or has fields we don't
+/// know about can't eat into whatever comes after it on the connection.
+#[derive(Debug, Clone)]
+pub struct RawPacket {
+    pub id: i32,
+    pub payload: Bytes
+}
+
+impl RawPacket {
+    pub fn new(id: i32, payload: Bytes) -> Self {
+        RawPacket {
+            id,
+            payload
+        }
+    }
+
+    /// Splits a frame's `id | payload` body, as read off the wire without its length.
+    pub fn from_frame(mut frame: Bytes) -> Result<Self> {
+        let (id, length) = read_varint(&frame)?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Packet is missing its id."))?;
+
+        Ok(RawPacket::new(id, frame.split_off(length)))
+    }
+
+    /// Encodes `packet` as it's sent under protocol `version`.
+    pub async fn encode<T: Out + Send + Sync>(packet: T, version: i32) -> Result<Self> {
+        let id = packet.get_id(version)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{:?} doesn't exist in protocol version {}.", T::KIND, version)))?;
+
+        let mut payload = Vec::new();
+        packet.write(&mut payload, version).await?;
+
+        Ok(RawPacket::new(id, payload.into()))
+    }
+
+    /// Decodes the payload as `T`, treating a packet that isn't a `T` under protocol `version` or
+    /// any bytes `T` leaves unread as an error.
+    pub async fn decode<T: In + Send + Sync>(&self, version: i32) -> Result<T> {
+        T::decode(self, version).await
+    }
+}
+
+impl Packet for RawPacket {
+    const KIND: Kind = Kind::Raw;
+
+    fn get_id(&self, _version: i32) -> Option<i32> {
+        Some(self.id)
+    }
+}
+
+#[async_trait]
+impl In for RawPacket {
+    /// Reads `id | payload` up to the end of `buffer`.
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> Result<Self> where Self: Sized {
+        let id = buffer.read_varint().await?;
+
+        let mut payload = Vec::new();
+        buffer.read_to_end(&mut payload).await?;
+
+        Ok(RawPacket::new(id, payload.into()))
+    }
+
+    async fn decode(packet: &RawPacket, _version: i32) -> Result<Self> where Self: Sized {
+        Ok(packet.clone())
+    }
+}
+
+#[async_trait]
+impl Out for RawPacket {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> Result<()> {
+        buffer.write_all(&self.payload).await
+    }
+}
+
+/// Decodes `packet` as `T` on behalf of `In::decode`.
+pub(crate) async fn decode<T: In + Send + Sync>(packet: &RawPacket, version: i32) -> Result<T> {
+    if registry::id_of(T::KIND, version) != Some(packet.id) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Expected {:?} but got packet {:#04x}.", T::KIND, packet.id)));
+    }
+
+    let mut cursor = Cursor::new(packet.payload.as_ref());
+    let decoded = T::read(&mut cursor, version).await?;
+
+    let leftover = packet.payload.len() - cursor.position() as usize;
+    if leftover > 0 {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Packet has {} unread bytes.", leftover)));
+    }
+
+    Ok(decoded)
+}
+
+/// Reads a VarInt from the front of `buffer`, returning it along with how many bytes it took up,
+/// or `None` if `buffer` ends before the VarInt does.
+pub(crate) fn read_varint(buffer: &[u8]) -> Result<Option<(i32, usize)>> {
+    let mut value = 0;
+
+    for (counter, byte) in buffer.iter().enumerate() {
+        if counter >= 5 {
+            return Err(Error::new(ErrorKind::InvalidData, "VarInt is too big."));
+        }
+
+        value |= ((*byte as i32) & 0b01111111) << (counter * 7);
+
+        if byte & 0b10000000 == 0 {
+            return Ok(Some((value, counter + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+
+    loop {
+        let mut temp = (value & 0b01111111) as u8;
+
+        value >>= 7;
+        if value != 0 {
+            temp |= 0b10000000;
+        }
+
+        buffer.push(temp);
+
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_varints_off_the_front_of_a_buffer() {
+        assert_eq!(read_varint(&[0x00]).unwrap(), Some((0, 1)));
+        assert_eq!(read_varint(&[0xdd, 0xc7, 0x01, 0xff]).unwrap(), Some((25565, 3)));
+        assert_eq!(read_varint(&[0xff, 0xff, 0xff, 0xff, 0x0f]).unwrap(), Some((-1, 5)));
+        assert_eq!(read_varint(&[0xff, 0xff]).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_varints_longer_than_five_bytes() {
+        assert!(read_varint(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        for value in &[0, 1, 127, 128, 255, 25565, 2097151, i32::MAX, -1, i32::MIN] {
+            let mut buffer = Vec::new();
+            write_varint(&mut buffer, *value);
+            assert_eq!(read_varint(&buffer).unwrap(), Some((*value, buffer.len())));
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_varints_stop_at_five_bytes() {
+        let mut bytes: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(bytes.read_varint().await.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let mut bytes: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert_eq!(bytes.read_varint().await.unwrap(), -1);
+    }
+
+    #[tokio::test]
+    async fn rejects_frames_out_of_bounds() {
+        let mut negative: &[u8] = &[0xff, 0xff, 0xff, 0xff, 0x0f];
+        assert_eq!(negative.read_frame().await.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, MAX_FRAME_SIZE as i32 + 1);
+        assert_eq!(buffer.as_slice().read_frame().await.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn splits_frames_into_id_and_payload() {
+        let mut bytes: &[u8] = &[0x03, 0x2a, 0x01, 0x02, 0xff];
+        let packet = bytes.read_frame().await.unwrap();
+
+        assert_eq!(packet.id, 0x2a);
+        assert_eq!(packet.payload.as_ref(), &[0x01, 0x02]);
+        assert_eq!(bytes, &[0xff]);
+    }
+
+    #[tokio::test]
+    async fn strings_can_not_claim_more_than_is_there() {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, i32::MAX);
+        buffer.extend_from_slice(b"short");
+        assert!(buffer.as_slice().read_string().await.is_err());
+
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, 10);
+        buffer.extend_from_slice(b"short");
+        assert_eq!(buffer.as_slice().read_string().await.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn decoding_leaves_no_bytes_unread() {
+        use crate::packet::version::V1_8;
+
+        let packet = RawPacket::new(0x00, Bytes::from_static(&[0x00, 0x00]));
+        assert!(packet.decode::<crate::packet::handshake::Request>(V1_8).await.is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use crate::packet::{In, Out, AsyncPacketReadExt, AsyncPacketWriteExt};
 use crate::packet;
 use async_trait::async_trait;
-use std::io::{Error, ErrorKind};
+use crate::packet::registry::Kind;
 use crate::player::Player;
+use crate::packet::Chat;
 use serde::Serialize;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct Packet {
@@ -23,21 +25,37 @@ pub struct Ping {
 pub struct Response {
     pub version: Version,
     pub players: Players,
-    pub description: Description,
+    pub description: Chat,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub favicon: Option<String>
 }
 
-#[derive(Serialize)]
-pub struct Description {
-    pub text: String
+/// A status response kept as the JSON it came as, for passing along what a backend said.
+pub struct RawResponse {
+    pub json: String
 }
 
 #[derive(Serialize)]
 pub struct Players {
     pub max: i32,
     pub online: i32,
-    pub sample: Vec<Player>
+    pub sample: Vec<SamplePlayer>
+}
+
+/// A player as the status sample lists them, which is only their name and UUID.
+#[derive(Serialize)]
+pub struct SamplePlayer {
+    pub name: String,
+    pub id: Uuid
+}
+
+impl From<Player> for SamplePlayer {
+    fn from(player: Player) -> Self {
+        SamplePlayer {
+            name: player.name,
+            id: player.id
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -48,38 +66,29 @@ pub struct Version {
 
 
 impl packet::Packet for Packet {
-    fn get_id(&self) -> i32 {
-        0
-    }
+    const KIND: Kind = Kind::Handshake;
 }
 
 #[async_trait]
 impl In for Packet {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Self> where Self: Sized {
-        let _ = buffer.read_varint().await?; //todo: maybe offload this kind of logic to calling function
-        let id = buffer.read_varint().await?;
-
-        if id != 0x00 {
-            return Err(Error::new(ErrorKind::Other, "Invalid handshake packet."));
-        }
-        
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
         let version: i32 = buffer.read_varint().await?;
         let address = buffer.read_string().await?;
         let port = buffer.read_u16().await?;
         let next_state: i32 = buffer.read_varint().await?;
 
         Ok(Packet {
-            version: version,
-            address: address,
-            port: port,
-            next_state: next_state
+            version,
+            address,
+            port,
+            next_state
         })
     }
 }
 
 #[async_trait]
 impl Out for Packet {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
         buffer.write_varint(self.version).await?;
         buffer.write_string(self.address).await?;
         buffer.write_u16(self.port).await?;
@@ -89,21 +98,12 @@ impl Out for Packet {
 }
 
 impl packet::Packet for Ping {
-    fn get_id(&self) -> i32 {
-        1
-    }
+    const KIND: Kind = Kind::Ping;
 }
 
 #[async_trait]
 impl In for Ping {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Self> where Self: Sized {
-        let _ = buffer.read_varint().await?; //todo: maybe offload this kind of logic to calling function
-        let id = buffer.read_varint().await?;
-
-        if id != 0x01 {
-            return Err(Error::new(ErrorKind::Other, "Invalid ping packet."));
-        }
-        
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
         Ok(Ping {
             _fluff: buffer.read_long().await?
         })
@@ -113,7 +113,7 @@ impl In for Ping {
 
 #[async_trait]
 impl Out for Ping {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
         buffer.write_long(self._fluff).await?;
         Ok(())
     }
@@ -121,36 +121,75 @@ impl Out for Ping {
 
 
 impl packet::Packet for Request {
-    fn get_id(&self) -> i32 {
-        0
-    }
+    const KIND: Kind = Kind::StatusRequest;
 }
 
 #[async_trait]
 impl In for Request {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Self> where Self: Sized {
-        let _ = buffer.read_varint().await?; //todo: maybe offload this kind of logic to calling function
-        let id = buffer.read_varint().await?;
-
-        if id != 0x00 {
-            return Err(Error::new(ErrorKind::Other, "Invalid request packet."));
-        }
-        
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(_buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
         Ok(Request)
     }
 }
 
-impl packet::Packet for Response {
-    fn get_id(&self) -> i32 {
-        0
+#[async_trait]
+impl Out for Request {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, _buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
+impl packet::Packet for Response {
+    const KIND: Kind = Kind::StatusResponse;
+}
+
 
 #[async_trait]
 impl Out for Response {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(mut self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        self.description = self.description.for_version(version);
         buffer.write_string(serde_json::to_string(&self).unwrap()).await?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl packet::Packet for RawResponse {
+    const KIND: Kind = Kind::StatusResponse;
+}
+
+#[async_trait]
+impl In for RawResponse {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(RawResponse {
+            json: buffer.read_string().await?
+        })
+    }
+}
+
+#[async_trait]
+impl Out for RawResponse {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_string(self.json).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn samples_leave_out_everything_but_name_and_id() {
+        let mut player = Player::offline("Notch");
+        let mut textures = HashMap::new();
+        textures.insert("name".to_owned(), "textures".to_owned());
+        textures.insert("value".to_owned(), "secret".to_owned());
+        player.properties.push(textures);
+
+        let json = serde_json::to_value(SamplePlayer::from(player)).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "name": "Notch",
+            "id": "b50ad385-829d-3141-a216-7e7d7539ba7f"
+        }));
+    }
+}
@@ -1,9 +1,10 @@
 use crate::packet::{In, Out, AsyncPacketReadExt, AsyncPacketWriteExt};
 use crate::packet::{Packet};
 use async_trait::async_trait;
+use crate::packet::registry::Kind;
+use crate::packet::version;
 use std::io::{Error, ErrorKind};
-use crate::player::Player;
-use serde::Serialize;
+use uuid::Uuid;
 use openssl::rsa::{Rsa, Padding};
 use serde_json;
 
@@ -14,21 +15,12 @@ pub struct Start {
 
 
 impl Packet for Start {
-    fn get_id(&self) -> i32 {
-        0
-    }
+    const KIND: Kind = Kind::LoginStart;
 }
 
 #[async_trait]
 impl In for Start {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Self> where Self: Sized {
-        let _ = buffer.read_varint().await?; //todo: maybe offload this kind of logic to calling function
-        let id = buffer.read_varint().await?;
-
-        if id != 0x00 {
-            return Err(Error::new(ErrorKind::Other, "Invalid login start packet."));
-        }
-        
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
         Ok(Start {
             name: buffer.read_string().await?
         })
@@ -37,7 +29,7 @@ impl In for Start {
 
 #[async_trait]
 impl Out for Start {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
         buffer.write_string(self.name).await?;
         Ok(())
     }
@@ -53,20 +45,18 @@ pub struct EncryptionRequest {
 
 
 impl Packet for EncryptionRequest {
-    fn get_id(&self) -> i32 {
-        1
-    }
+    const KIND: Kind = Kind::EncryptionRequest;
 }
 
 
 #[async_trait]
 impl Out for EncryptionRequest {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
         buffer.write_string(self.id).await?;
         buffer.write_varint(self.public_key.len() as i32).await?;
-        buffer.write(&self.public_key).await?;
+        buffer.write_all(&self.public_key).await?;
         buffer.write_varint(self.token.len() as i32).await?;
-        buffer.write(&self.token).await?;
+        buffer.write_all(&self.token).await?;
         Ok(())
     }
 }
@@ -78,46 +68,56 @@ pub struct EncryptionResponse {
 }
 
 impl Packet for EncryptionResponse {
-    fn get_id(&self) -> i32 {
-        1
-    }
+    const KIND: Kind = Kind::EncryptionResponse;
 }
 
 impl EncryptionResponse {
-    pub fn decrypt_token(&self, key: &Rsa<openssl::pkey::Private>, length: usize) -> Vec<u8> {
-        let mut to_return: Vec<u8> = vec![0; self.token.len() as usize];
-        let read = key.private_decrypt(&self.token, &mut to_return, Padding::PKCS1).unwrap();
-
-        to_return[..length].to_vec()
+    pub fn decrypt_token(&self, key: &Rsa<openssl::pkey::Private>) -> std::io::Result<Vec<u8>> {
+        decrypt(key, &self.token)
     }
 
-    pub fn decrypt_secret(&self, key: &Rsa<openssl::pkey::Private>) -> Vec<u8> {
-        let mut to_return: Vec<u8> = vec![0; self.secret.len() as usize];
-        let read = key.private_decrypt(&self.secret, &mut to_return, Padding::PKCS1).unwrap();
+    pub fn decrypt_secret(&self, key: &Rsa<openssl::pkey::Private>) -> std::io::Result<Vec<u8>> {
+        let secret = decrypt(key, &self.secret)?;
+        if secret.len() != 16 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Shared secret is {} bytes long instead of 16.", secret.len())));
+        }
 
-        to_return[..16].to_vec()
+        Ok(secret)
     }
 }
 
+// both fields are encrypted with our RSA key, nothing legitimate comes anywhere near this long
+async fn read_encrypted<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Vec<u8>> {
+    let length = buffer.read_varint().await?;
+    if !(0..=512).contains(&length) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Encrypted field length {} is out of bounds.", length)));
+    }
+
+    let mut data = vec![0; length as usize];
+    buffer.read_exact(&mut data).await?;
+
+    Ok(data)
+}
+
+fn decrypt(key: &Rsa<openssl::pkey::Private>, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut decrypted = vec![0; key.size() as usize];
+    let length = key.private_decrypt(data, &mut decrypted, Padding::PKCS1)
+        .map_err(|error| Error::new(ErrorKind::InvalidData, error))?;
+
+    decrypted.truncate(length);
+    Ok(decrypted)
+}
+
 
 #[async_trait]
 impl In for EncryptionResponse {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Self> where Self: Sized {
-        let _ = buffer.read_varint().await?; //todo: maybe offload this kind of logic to calling function
-        let id = buffer.read_varint().await?;
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        let secret = read_encrypted(buffer).await?;
+        let token = read_encrypted(buffer).await?;
 
-        if id != 0x01 {
-            return Err(Error::new(ErrorKind::Other, "Invalid encryption response packet."));
-        }
-
-        let mut secret = vec![0; buffer.read_varint().await? as usize];
-        buffer.read_exact(&mut secret).await?;
 
-        let mut token = vec![0; buffer.read_varint().await? as usize];
-        buffer.read_exact(&mut token).await?;
-        
         Ok(EncryptionResponse {
-            secret: secret,
+            secret,
             token: token.to_vec()
         })
     }
@@ -126,22 +126,26 @@ impl In for EncryptionResponse {
 
 #[derive(Debug)]
 pub struct Success {
-    pub uuid: String,
+    pub uuid: Uuid,
     pub name: String
 }
 
 
 impl Packet for Success {
-    fn get_id(&self) -> i32 {
-        2
-    }
+    const KIND: Kind = Kind::LoginSuccess;
 }
 
 
 #[async_trait]
 impl Out for Success {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
-        buffer.write_string(self.uuid).await?;
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        // 1.16 started sending the uuid as raw bytes instead of a hyphenated string
+        if version >= version::V1_16 {
+            buffer.write_uuid(self.uuid).await?;
+        } else {
+            buffer.write_string(self.uuid.to_hyphenated().to_string()).await?;
+        }
+
         buffer.write_string(self.name).await?;
         Ok(())
     }
@@ -149,19 +153,45 @@ impl Out for Success {
 
 #[async_trait]
 impl In for Success {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> std::io::Result<Self> where Self: Sized {
-        let size = buffer.read_varint().await?; //todo: maybe offload this kind of logic to calling function
-        let id = buffer.read_varint().await?;
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        let uuid = if version >= version::V1_16 {
+            buffer.read_uuid().await?
+        } else {
+            Uuid::parse_str(&buffer.read_string().await?)
+                .map_err(|error| Error::new(ErrorKind::InvalidData, error.to_string()))?
+        };
 
-        if id != 0x02 {
-            return Err(Error::new(ErrorKind::Other, format!("{}", id)));
-        }
+        Ok(Success {
+            uuid,
+            name: buffer.read_string().await?
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct SetCompression {
+    pub threshold: i32
+}
 
-        // im assuming offline mode servers dont send back a uuid or name in this packet ?
 
-        Ok(Success {
-            uuid: String::from("N/A"),
-            name: String::from("N/A")
+impl Packet for SetCompression {
+    const KIND: Kind = Kind::SetCompression;
+}
+
+
+#[async_trait]
+impl Out for SetCompression {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_varint(self.threshold).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl In for SetCompression {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(SetCompression {
+            threshold: buffer.read_varint().await?
         })
     }
 }
@@ -173,18 +203,100 @@ pub struct Disconnect {
 
 
 impl Packet for Disconnect {
-    fn get_id(&self) -> i32 {
-        0
-    }
+    const KIND: Kind = Kind::LoginDisconnect;
 }
 
 
 #[async_trait]
 impl Out for Disconnect {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> std::io::Result<()> {
-        buffer.write_string(serde_json::to_string(&self.chat)?).await?;
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        buffer.write_string(serde_json::to_string(&self.chat.for_version(version))?).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl In for Disconnect {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(Disconnect {
+            chat: serde_json::from_str(&buffer.read_string().await?)?
+        })
+    }
+}
+
+/// Lets a server ask the client (or rather us) things during login, 1.13+.
+#[derive(Debug)]
+pub struct PluginRequest {
+    pub message_id: i32,
+    pub channel: String,
+    pub data: Vec<u8>
+}
+
+impl Packet for PluginRequest {
+    const KIND: Kind = Kind::LoginPluginRequest;
+}
+
+#[async_trait]
+impl In for PluginRequest {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        let message_id = buffer.read_varint().await?;
+        let channel = buffer.read_string().await?;
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data).await?;
+
+        Ok(PluginRequest {
+            message_id,
+            channel,
+            data
+        })
+    }
+}
+
+#[async_trait]
+impl Out for PluginRequest {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_varint(self.message_id).await?;
+        buffer.write_string(self.channel).await?;
+        buffer.write_all(&self.data).await?;
         Ok(())
     }
 }
 
+/// The answer to a `PluginRequest` with the same `message_id`. Unknown channels are answered
+/// with `successful` set to false and no data.
+#[derive(Debug)]
+pub struct PluginResponse {
+    pub message_id: i32,
+    pub successful: bool,
+    pub data: Vec<u8>
+}
+
+impl Packet for PluginResponse {
+    const KIND: Kind = Kind::LoginPluginResponse;
+}
+
+#[async_trait]
+impl In for PluginResponse {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        let message_id = buffer.read_varint().await?;
+        let successful = buffer.read_bool().await?;
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data).await?;
+
+        Ok(PluginResponse {
+            message_id,
+            successful,
+            data
+        })
+    }
+}
 
+#[async_trait]
+impl Out for PluginResponse {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_varint(self.message_id).await?;
+        buffer.write_bool(self.successful).await?;
+        buffer.write_all(&self.data).await?;
+        Ok(())
+    }
+}
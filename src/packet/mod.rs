@@ -1,31 +1,44 @@
 pub mod handshake;
 pub mod login;
-use log::{trace};
+pub mod encryption;
+pub mod compression;
+pub mod frame;
+pub mod registry;
+pub mod version;
+pub mod play;
+pub mod chat;
+mod nbt;
 
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+use crate::packet::frame::{RawPacket, MAX_FRAME_SIZE};
+use crate::packet::registry::Kind;
 use async_trait::async_trait;
-use std::io::{Error, ErrorKind};
-use openssl::rsa::{Rsa, Padding};
-use aes::Aes128;
-use cfb8::Cfb8;
-use cfb8::stream_cipher::{NewStreamCipher, StreamCipher};
-use serde::{Serialize, Deserialize};
-use crate::util::color::Color;
+use uuid::Uuid;
 
-type AesCfb8 = Cfb8<Aes128>;
+pub use crate::packet::chat::Chat;
 
 pub trait Packet {
- fn get_id(&self) -> i32;
+    const KIND: Kind;
+
+    /// The id this packet is sent with under protocol `version`, if that version has it at all.
+    fn get_id(&self, version: i32) -> Option<i32> {
+        registry::id_of(Self::KIND, version)
+    }
 }
 
 #[async_trait]
 pub trait Out : Packet {
-    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W) -> Result<()>;
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> Result<()>;
 }
 #[async_trait]
 pub trait In : Packet {
-    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> Result<Self> where Self: Sized;
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> Result<Self> where Self: Sized;
+
+    /// Decodes a packet that was read off the wire without being looked at.
+    async fn decode(packet: &RawPacket, version: i32) -> Result<Self> where Self: Sized + Send + Sync {
+        frame::decode(packet, version).await
+    }
 }
 
 #[async_trait]
@@ -34,8 +47,9 @@ pub trait AsyncPacketWriteExt : AsyncWriteExt {
     async fn write_long(&mut self, value: i64) -> Result<()>;
     async fn write_string(&mut self, value: String) -> Result<()>;
     async fn write_ushort(&mut self, value: u16) -> Result<()>;
-    async fn write_packet<T: Packet + Out + Send + Sync>(&mut self, packet: T) -> Result<()>;
-    async fn write_packet_encrypted<T: Packet + Out + Send + Sync>(&mut self, packet: T, secret: &[u8]) -> Result<()>;
+    async fn write_bool(&mut self, value: bool) -> Result<()>;
+    async fn write_uuid(&mut self, value: Uuid) -> Result<()>;
+    async fn write_packet<T: Packet + Out + Send + Sync>(&mut self, packet: T, version: i32) -> Result<()>;
 }
 
 #[async_trait]
@@ -44,8 +58,10 @@ pub trait AsyncPacketReadExt : AsyncReadExt {
     async fn read_string(&mut self) -> Result<String>;
     async fn read_long(&mut self) -> Result<i64>;
     async fn read_ushort(&mut self) -> Result<u16>;
-    async fn receive<T: Packet + In + Send + Sync>(&mut self) -> Result<T>;
-    async fn receive_encrypted<T: Packet + In + Send + Sync>(&mut self, secret: &[u8]) -> Result<T>;
+    async fn read_bool(&mut self) -> Result<bool>;
+    async fn read_uuid(&mut self) -> Result<Uuid>;
+    async fn receive<T: Packet + In + Send + Sync>(&mut self, version: i32) -> Result<T>;
+    async fn read_frame(&mut self) -> Result<RawPacket>;
 }
 
 #[async_trait]
@@ -71,7 +87,7 @@ impl<W: AsyncWrite + Unpin + Send + Sync> AsyncPacketWriteExt for W {
             }
         }
 
-        self.write_all(&mut buffer[0..counter]).await?;
+        self.write_all(&buffer[0..counter]).await?;
 
         Ok(())
     }
@@ -95,34 +111,33 @@ impl<W: AsyncWrite + Unpin + Send + Sync> AsyncPacketWriteExt for W {
         Ok(())
     }
 
-    async fn write_packet<T: Packet + Out + Send + Sync>(&mut self, packet: T) -> Result<()> {
-        let mut buffer = Vec::new();
-        let mut payload = Vec::new();
-
-        buffer.write_varint(packet.get_id()).await?;
-        packet.write(&mut buffer).await?;
-
-        payload.write_varint(buffer.len() as i32).await?;
-        payload.write_all(&buffer).await?;
-
-        self.write_all(&payload).await?;
+    async fn write_bool(&mut self, value: bool) -> Result<()> {
+        self.write_u8(value as u8).await?;
+        Ok(())
+    }
 
+    async fn write_uuid(&mut self, value: Uuid) -> Result<()> {
+        self.write_all(value.as_bytes()).await?;
         Ok(())
     }
 
-    async fn write_packet_encrypted<T: Packet + Out + Send + Sync>(&mut self, packet: T, secret: &[u8]) -> Result<()> {
+    async fn write_packet<T: Packet + Out + Send + Sync>(&mut self, packet: T, version: i32) -> Result<()> {
+        let id = packet.get_id(version)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{:?} doesn't exist in protocol version {}.", T::KIND, version)))?;
+
         let mut buffer = Vec::new();
-        let mut payload = Vec::new();
 
-        buffer.write_varint(packet.get_id()).await?;
-        packet.write(&mut buffer).await?;
+        buffer.write_varint(id).await?;
+        packet.write(&mut buffer, version).await?;
+
+        let mut payload = Vec::new();
 
         payload.write_varint(buffer.len() as i32).await?;
         payload.write_all(&buffer).await?;
 
-        AesCfb8::new_var(secret, secret).unwrap().encrypt(&mut payload);
-
         self.write_all(&payload).await?;
+        // wrappers like EncryptedStream may hold on to part of the packet until flushed
+        self.flush().await?;
 
         Ok(())
     }
@@ -136,6 +151,10 @@ impl<R: AsyncRead + Unpin + Send + Sync> AsyncPacketReadExt for R {
         let mut value = 0;
 
         loop {
+            if counter >= 5 {
+                return Err(Error::new(ErrorKind::InvalidData, "VarInt is too big."));
+            }
+
             self.read_exact(&mut buffer).await?;
 
             let temp = (buffer[0] as i32) & 0b01111111;
@@ -143,10 +162,6 @@ impl<R: AsyncRead + Unpin + Send + Sync> AsyncPacketReadExt for R {
             value |= temp << (counter * 7);
             counter += 1;
 
-            if counter > 5 {
-                panic!("invalid data");
-            }
-
             if buffer[0] & 0b10000000 == 0 {
                 break;
             }
@@ -173,61 +188,47 @@ impl<R: AsyncRead + Unpin + Send + Sync> AsyncPacketReadExt for R {
         Ok(result)
     }
 
+    async fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8().await? != 0)
+    }
 
-    async fn read_string(&mut self) -> Result<String> {
-        let size = self.read_varint().await?;
-        let mut buffer = vec![0; size as usize];
-        
+    async fn read_uuid(&mut self) -> Result<Uuid> {
+        let mut buffer = [0; 16];
         self.read_exact(&mut buffer).await?;
-        
-        let string = String::from_utf8(buffer).unwrap();
 
-        return Ok(string)
+        Ok(Uuid::from_bytes(buffer))
     }
 
-    async fn receive<T: Packet + In + Send + Sync>(&mut self) -> Result<T> {
-        return T::read(self).await;    
-    }
 
-    async fn receive_encrypted<T: Packet + In + Send + Sync>(&mut self, secret: &[u8]) -> Result<T> {
-        let mut time: i32 = 0;
-
-        loop {
-            let mut all = Vec::new();
-            let bytes = self.read_to_end(&mut all).await?;
-
-            AesCfb8::new_var(secret, secret).unwrap().decrypt(&mut all);
-            let mut reader = std::io::Cursor::new(all);
-            let packet = T::read(&mut reader).await;
-            
-            if packet.is_ok() {
-                return Ok(packet.unwrap());
-            }
-
-            time += 1;
-            tokio::time::delay_for(tokio::time::Duration::from_secs(1)).await;
+    async fn read_string(&mut self) -> Result<String> {
+        let size = self.read_varint().await?;
+        if size < 0 || size as usize > MAX_FRAME_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("String length {} is out of bounds.", size)));
+        }
 
-            if time > 3 {
-                trace!("Packet read timed out.");
-                return Err(Error::new(ErrorKind::Other, "Packet read timed out."));
-            }
+        // only what's actually there gets allocated, not whatever length the peer claims
+        let mut buffer = Vec::new();
+        self.take(size as u64).read_to_end(&mut buffer).await?;
+        if buffer.len() != size as usize {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "String is cut short."));
         }
+
+        String::from_utf8(buffer).map_err(|error| Error::new(ErrorKind::InvalidData, error))
     }
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Chat {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub text: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub translate: Option<String>
-}
+    async fn receive<T: Packet + In + Send + Sync>(&mut self, version: i32) -> Result<T> {
+        T::decode(&self.read_frame().await?, version).await
+    }
 
-impl Chat {
-    pub fn new<S: Into<String>>(text: S) -> Self {
-        Chat {
-            text: Some(text.into().colored()),
-            translate: None
+    async fn read_frame(&mut self) -> Result<RawPacket> {
+        let length = self.read_varint().await?;
+        if length < 0 || length as usize > MAX_FRAME_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Packet length {} is out of bounds.", length)));
         }
+
+        let mut data = vec![0; length as usize];
+        self.read_exact(&mut data).await?;
+
+        RawPacket::from_frame(data.into())
     }
-}
\ No newline at end of file
+}
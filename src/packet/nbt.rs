@@ -0,0 +1,135 @@
+use std::io::{Error, ErrorKind, Result};
+use crate::packet::AsyncPacketReadExt;
+use crate::packet::frame::MAX_FRAME_SIZE;
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+enum Container {
+    Compound,
+    List { tag: u8, remaining: i32 }
+}
+
+/// Reads a complete named NBT tag without interpreting it, returning its raw bytes so it can be
+/// written back out as is.
+pub async fn read_raw<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R) -> Result<Vec<u8>> {
+    let mut raw = Vec::new();
+
+    let tag = copy_u8(buffer, &mut raw).await?;
+    if tag == TAG_END {
+        return Ok(raw);
+    }
+
+    copy_string(buffer, &mut raw).await?;
+
+    // compounds and lists are walked with an explicit stack rather than recursion
+    let mut stack = Vec::new();
+    copy_payload(buffer, &mut raw, &mut stack, tag).await?;
+
+    while let Some(container) = stack.last_mut() {
+        let tag = match container {
+            Container::Compound => {
+                let tag = copy_u8(buffer, &mut raw).await?;
+                if tag == TAG_END {
+                    stack.pop();
+                    continue;
+                }
+
+                copy_string(buffer, &mut raw).await?;
+                tag
+            },
+            Container::List { tag, remaining } => {
+                if *remaining <= 0 {
+                    stack.pop();
+                    continue;
+                }
+
+                *remaining -= 1;
+                *tag
+            }
+        };
+
+        copy_payload(buffer, &mut raw, &mut stack, tag).await?;
+    }
+
+    Ok(raw)
+}
+
+async fn copy_payload<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, raw: &mut Vec<u8>, stack: &mut Vec<Container>, tag: u8) -> Result<()> {
+    match tag {
+        TAG_BYTE => copy(buffer, raw, 1).await,
+        TAG_SHORT => copy(buffer, raw, 2).await,
+        TAG_INT | TAG_FLOAT => copy(buffer, raw, 4).await,
+        TAG_LONG | TAG_DOUBLE => copy(buffer, raw, 8).await,
+        TAG_BYTE_ARRAY => {
+            let length = copy_length(buffer, raw).await?;
+            copy(buffer, raw, length).await
+        },
+        TAG_STRING => copy_string(buffer, raw).await,
+        TAG_LIST => {
+            let tag = copy_u8(buffer, raw).await?;
+            let remaining = copy_length(buffer, raw).await? as i32;
+
+            stack.push(Container::List { tag, remaining });
+            Ok(())
+        },
+        TAG_COMPOUND => {
+            stack.push(Container::Compound);
+            Ok(())
+        },
+        TAG_INT_ARRAY => {
+            let length = copy_length(buffer, raw).await?;
+            copy(buffer, raw, length * 4).await
+        },
+        TAG_LONG_ARRAY => {
+            let length = copy_length(buffer, raw).await?;
+            copy(buffer, raw, length * 8).await
+        },
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("Unknown NBT tag {}.", tag)))
+    }
+}
+
+async fn copy<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, raw: &mut Vec<u8>, length: usize) -> Result<()> {
+    let start = raw.len();
+    raw.resize(start + length, 0);
+    buffer.read_exact(&mut raw[start..]).await?;
+
+    Ok(())
+}
+
+async fn copy_u8<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, raw: &mut Vec<u8>) -> Result<u8> {
+    let value = buffer.read_u8().await?;
+    raw.push(value);
+
+    Ok(value)
+}
+
+async fn copy_length<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, raw: &mut Vec<u8>) -> Result<usize> {
+    let length = buffer.read_i32().await?;
+    // nothing longer than the frame it's in can be valid
+    if length < 0 || length as usize > MAX_FRAME_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, format!("NBT length {} is out of bounds.", length)));
+    }
+
+    raw.extend_from_slice(&length.to_be_bytes());
+
+    Ok(length as usize)
+}
+
+async fn copy_string<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, raw: &mut Vec<u8>) -> Result<()> {
+    let length = buffer.read_ushort().await?;
+    raw.extend_from_slice(&length.to_be_bytes());
+
+    copy(buffer, raw, length as usize).await
+}
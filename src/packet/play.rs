@@ -0,0 +1,946 @@
+use crate::packet::{In, Out, AsyncPacketReadExt, AsyncPacketWriteExt};
+use crate::packet::{Packet, Chat};
+use crate::packet::registry::Kind;
+use crate::packet::version;
+use crate::packet::nbt;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct ClientboundKeepAlive {
+    pub id: i64
+}
+
+impl Packet for ClientboundKeepAlive {
+    const KIND: Kind = Kind::ClientboundKeepAlive;
+}
+
+#[async_trait]
+impl In for ClientboundKeepAlive {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(ClientboundKeepAlive {
+            id: read_keep_alive_id(buffer, version).await?
+        })
+    }
+}
+
+#[async_trait]
+impl Out for ClientboundKeepAlive {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        write_keep_alive_id(buffer, self.id, version).await
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerboundKeepAlive {
+    pub id: i64
+}
+
+impl Packet for ServerboundKeepAlive {
+    const KIND: Kind = Kind::ServerboundKeepAlive;
+}
+
+#[async_trait]
+impl In for ServerboundKeepAlive {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(ServerboundKeepAlive {
+            id: read_keep_alive_id(buffer, version).await?
+        })
+    }
+}
+
+#[async_trait]
+impl Out for ServerboundKeepAlive {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        write_keep_alive_id(buffer, self.id, version).await
+    }
+}
+
+// keep alive ids were VarInts until 1.12.2
+async fn read_keep_alive_id<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<i64> {
+    if version >= version::V1_12_2 {
+        buffer.read_long().await
+    } else {
+        Ok(buffer.read_varint().await? as i64)
+    }
+}
+
+async fn write_keep_alive_id<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(buffer: &mut W, id: i64, version: i32) -> std::io::Result<()> {
+    if version >= version::V1_12_2 {
+        buffer.write_long(id).await
+    } else {
+        buffer.write_varint(id as i32).await
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientboundChat {
+    pub chat: Chat,
+    /// 0 for chat, 1 for system messages and 2 for the action bar.
+    pub position: u8,
+    /// Only sent to 1.16+ clients, nil for messages that aren't from a player.
+    pub sender: Uuid
+}
+
+impl Packet for ClientboundChat {
+    const KIND: Kind = Kind::ClientboundChat;
+}
+
+#[async_trait]
+impl In for ClientboundChat {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        let chat = serde_json::from_str(&buffer.read_string().await?)?;
+        let position = buffer.read_u8().await?;
+        let sender = if version >= version::V1_16 {
+            buffer.read_uuid().await?
+        } else {
+            Uuid::nil()
+        };
+
+        Ok(ClientboundChat {
+            chat,
+            position,
+            sender
+        })
+    }
+}
+
+#[async_trait]
+impl Out for ClientboundChat {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        buffer.write_string(serde_json::to_string(&self.chat.for_version(version))?).await?;
+        buffer.write_u8(self.position).await?;
+        if version >= version::V1_16 {
+            buffer.write_uuid(self.sender).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerboundChat {
+    pub message: String
+}
+
+impl Packet for ServerboundChat {
+    const KIND: Kind = Kind::ServerboundChat;
+}
+
+#[async_trait]
+impl In for ServerboundChat {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(ServerboundChat {
+            message: buffer.read_string().await?
+        })
+    }
+}
+
+#[async_trait]
+impl Out for ServerboundChat {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_string(self.message).await?;
+        Ok(())
+    }
+}
+
+/// What the client has typed so far, sent when the player presses Tab.
+#[derive(Debug)]
+pub struct TabCompleteRequest {
+    /// Echoed in the response, 1.13+ only.
+    pub transaction_id: i32,
+    pub text: String,
+    /// Whether the text is a command even without a leading slash, as typed into a command block.
+    /// 1.9 to 1.12 only.
+    pub assume_command: bool,
+    /// The block the player is looking at, before 1.13.
+    pub position: Option<i64>
+}
+
+impl Packet for TabCompleteRequest {
+    const KIND: Kind = Kind::TabCompleteRequest;
+}
+
+#[async_trait]
+impl In for TabCompleteRequest {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        if version >= version::V1_13 {
+            return Ok(TabCompleteRequest {
+                transaction_id: buffer.read_varint().await?,
+                text: buffer.read_string().await?,
+                assume_command: false,
+                position: None
+            });
+        }
+
+        let text = buffer.read_string().await?;
+        let assume_command = if version >= version::V1_9 {
+            buffer.read_bool().await?
+        } else {
+            false
+        };
+
+        let position = if buffer.read_bool().await? {
+            Some(buffer.read_long().await?)
+        } else {
+            None
+        };
+
+        Ok(TabCompleteRequest {
+            transaction_id: 0,
+            text,
+            assume_command,
+            position
+        })
+    }
+}
+
+#[async_trait]
+impl Out for TabCompleteRequest {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        if version >= version::V1_13 {
+            buffer.write_varint(self.transaction_id).await?;
+            return buffer.write_string(self.text).await;
+        }
+
+        buffer.write_string(self.text).await?;
+        if version >= version::V1_9 {
+            buffer.write_bool(self.assume_command).await?;
+        }
+
+        match self.position {
+            Some(position) => {
+                buffer.write_bool(true).await?;
+                buffer.write_long(position).await
+            },
+            None => buffer.write_bool(false).await
+        }
+    }
+}
+
+/// The completions for a `TabCompleteRequest`. Before 1.13 they replace the last word typed,
+/// from then on the range of the text given by `start` and `length`.
+#[derive(Debug)]
+pub struct TabCompleteResponse {
+    pub transaction_id: i32,
+    pub start: i32,
+    pub length: i32,
+    pub matches: Vec<String>
+}
+
+impl Packet for TabCompleteResponse {
+    const KIND: Kind = Kind::TabCompleteResponse;
+}
+
+#[async_trait]
+impl In for TabCompleteResponse {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        let mut response = TabCompleteResponse {
+            transaction_id: 0,
+            start: 0,
+            length: 0,
+            matches: Vec::new()
+        };
+
+        if version >= version::V1_13 {
+            response.transaction_id = buffer.read_varint().await?;
+            response.start = buffer.read_varint().await?;
+            response.length = buffer.read_varint().await?;
+        }
+
+        let count = buffer.read_varint().await?;
+        for _ in 0..count {
+            response.matches.push(buffer.read_string().await?);
+
+            // tooltips aren't kept, rift never sends any
+            if version >= version::V1_13 && buffer.read_bool().await? {
+                buffer.read_string().await?;
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Out for TabCompleteResponse {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        if version >= version::V1_13 {
+            buffer.write_varint(self.transaction_id).await?;
+            buffer.write_varint(self.start).await?;
+            buffer.write_varint(self.length).await?;
+        }
+
+        buffer.write_varint(self.matches.len() as i32).await?;
+        for text in self.matches {
+            buffer.write_string(text).await?;
+            if version >= version::V1_13 {
+                buffer.write_bool(false).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The commands a 1.13+ client knows about, as the Brigadier node graph it completes and checks
+/// commands with. Nodes refer to each other by their index in `nodes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclareCommands {
+    pub nodes: Vec<CommandNode>,
+    pub root: i32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandNode {
+    /// The node's type in the low two bits, then whether it's executable, redirects and asks for
+    /// suggestions, see the `NODE_*` constants.
+    pub flags: u8,
+    pub children: Vec<i32>,
+    pub redirect: Option<i32>,
+    /// Literals and arguments only.
+    pub name: Option<String>,
+    /// Arguments only, the parser's identifier along with its properties as they were sent.
+    pub parser: Option<(String, Vec<u8>)>,
+    /// Where suggestions come from, e.g. `minecraft:ask_server`, for arguments that say.
+    pub suggestions: Option<String>
+}
+
+pub const NODE_ROOT: u8 = 0x00;
+pub const NODE_LITERAL: u8 = 0x01;
+pub const NODE_ARGUMENT: u8 = 0x02;
+const NODE_TYPE: u8 = 0x03;
+pub const NODE_EXECUTABLE: u8 = 0x04;
+pub const NODE_REDIRECT: u8 = 0x08;
+pub const NODE_SUGGESTIONS: u8 = 0x10;
+
+impl CommandNode {
+    pub fn literal<S: Into<String>>(name: S, children: Vec<i32>) -> Self {
+        CommandNode {
+            flags: NODE_LITERAL | NODE_EXECUTABLE,
+            children,
+            redirect: None,
+            name: Some(name.into()),
+            parser: None,
+            suggestions: None
+        }
+    }
+
+    /// An argument taking everything to the end of the command, completed by asking the server.
+    pub fn greedy_argument<S: Into<String>>(name: S) -> Self {
+        CommandNode {
+            flags: NODE_ARGUMENT | NODE_EXECUTABLE | NODE_SUGGESTIONS,
+            children: Vec::new(),
+            redirect: None,
+            name: Some(name.into()),
+            // brigadier:string's greedy phrase mode
+            parser: Some((String::from("brigadier:string"), vec![2])),
+            suggestions: Some(String::from("minecraft:ask_server"))
+        }
+    }
+
+    pub fn kind(&self) -> u8 {
+        self.flags & NODE_TYPE
+    }
+}
+
+impl Packet for DeclareCommands {
+    const KIND: Kind = Kind::DeclareCommands;
+}
+
+#[async_trait]
+impl In for DeclareCommands {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        let count = buffer.read_varint().await?;
+        let mut nodes = Vec::new();
+
+        for _ in 0..count {
+            let flags = buffer.read_u8().await?;
+
+            let mut children = Vec::new();
+            for _ in 0..buffer.read_varint().await? {
+                children.push(buffer.read_varint().await?);
+            }
+
+            let redirect = match flags & NODE_REDIRECT {
+                0 => None,
+                _ => Some(buffer.read_varint().await?)
+            };
+
+            let name = match flags & NODE_TYPE {
+                NODE_ROOT => None,
+                _ => Some(buffer.read_string().await?)
+            };
+
+            let parser = match flags & NODE_TYPE {
+                NODE_ARGUMENT => {
+                    let parser = buffer.read_string().await?;
+                    let properties = read_parser_properties(buffer, &parser).await?;
+                    Some((parser, properties))
+                },
+                _ => None
+            };
+
+            let suggestions = match flags & NODE_SUGGESTIONS {
+                0 => None,
+                _ => Some(buffer.read_string().await?)
+            };
+
+            nodes.push(CommandNode {
+                flags,
+                children,
+                redirect,
+                name,
+                parser,
+                suggestions
+            });
+        }
+
+        Ok(DeclareCommands {
+            nodes,
+            root: buffer.read_varint().await?
+        })
+    }
+}
+
+#[async_trait]
+impl Out for DeclareCommands {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_varint(self.nodes.len() as i32).await?;
+
+        for node in self.nodes {
+            // whether there's a redirect or suggestions to read is up to what the node has
+            let mut flags = node.flags & !(NODE_REDIRECT | NODE_SUGGESTIONS);
+            if node.redirect.is_some() {
+                flags |= NODE_REDIRECT;
+            }
+            if node.suggestions.is_some() {
+                flags |= NODE_SUGGESTIONS;
+            }
+            buffer.write_u8(flags).await?;
+
+            buffer.write_varint(node.children.len() as i32).await?;
+            for child in node.children {
+                buffer.write_varint(child).await?;
+            }
+
+            if let Some(redirect) = node.redirect {
+                buffer.write_varint(redirect).await?;
+            }
+
+            if let Some(name) = node.name {
+                buffer.write_string(name).await?;
+            }
+
+            if let Some((parser, properties)) = node.parser {
+                buffer.write_string(parser).await?;
+                buffer.write_all(&properties).await?;
+            }
+
+            if let Some(suggestions) = node.suggestions {
+                buffer.write_string(suggestions).await?;
+            }
+        }
+
+        buffer.write_varint(self.root).await
+    }
+}
+
+/// Reads the properties of an argument parsed by `parser`, which differ from parser to parser.
+/// Parsers from mods are unknown, so are the lengths of their properties.
+async fn read_parser_properties<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, parser: &str) -> std::io::Result<Vec<u8>> {
+    // the bounds of a number argument follow its flags, each as wide as the number itself
+    let width = match parser {
+        "brigadier:double" | "brigadier:long" => 8,
+        "brigadier:float" | "brigadier:integer" => 4,
+        "brigadier:string" => {
+            let mut properties = Vec::new();
+            properties.write_varint(buffer.read_varint().await?).await?;
+            return Ok(properties);
+        },
+        "minecraft:entity" | "minecraft:score_holder" | "minecraft:range" => return Ok(vec![buffer.read_u8().await?]),
+        _ if parser.starts_with("minecraft:") || parser.starts_with("brigadier:") => return Ok(Vec::new()),
+        _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unknown argument parser {}.", parser)))
+    };
+
+    let flags = buffer.read_u8().await?;
+    let bounds = (flags & 0x01 != 0) as usize + (flags & 0x02 != 0) as usize;
+
+    let mut properties = vec![0; 1 + bounds * width];
+    properties[0] = flags;
+    buffer.read_exact(&mut properties[1..]).await?;
+
+    Ok(properties)
+}
+
+#[derive(Debug)]
+pub struct ClientboundPluginMessage {
+    pub channel: String,
+    pub data: Vec<u8>
+}
+
+impl Packet for ClientboundPluginMessage {
+    const KIND: Kind = Kind::ClientboundPluginMessage;
+}
+
+#[async_trait]
+impl In for ClientboundPluginMessage {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        let channel = buffer.read_string().await?;
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data).await?;
+
+        Ok(ClientboundPluginMessage {
+            channel,
+            data
+        })
+    }
+}
+
+#[async_trait]
+impl Out for ClientboundPluginMessage {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_string(self.channel).await?;
+        buffer.write_all(&self.data).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ServerboundPluginMessage {
+    pub channel: String,
+    pub data: Vec<u8>
+}
+
+impl Packet for ServerboundPluginMessage {
+    const KIND: Kind = Kind::ServerboundPluginMessage;
+}
+
+#[async_trait]
+impl In for ServerboundPluginMessage {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        let channel = buffer.read_string().await?;
+        let mut data = Vec::new();
+        buffer.read_to_end(&mut data).await?;
+
+        Ok(ServerboundPluginMessage {
+            channel,
+            data
+        })
+    }
+}
+
+#[async_trait]
+impl Out for ServerboundPluginMessage {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, _version: i32) -> std::io::Result<()> {
+        buffer.write_string(self.channel).await?;
+        buffer.write_all(&self.data).await?;
+        Ok(())
+    }
+}
+
+/// Kicks the player, the play state counterpart of `login::Disconnect`.
+#[derive(Debug)]
+pub struct Disconnect {
+    pub chat: Chat
+}
+
+impl Packet for Disconnect {
+    const KIND: Kind = Kind::PlayDisconnect;
+}
+
+#[async_trait]
+impl In for Disconnect {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, _version: i32) -> std::io::Result<Self> where Self: Sized {
+        Ok(Disconnect {
+            chat: serde_json::from_str(&buffer.read_string().await?)?
+        })
+    }
+}
+
+#[async_trait]
+impl Out for Disconnect {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        buffer.write_string(serde_json::to_string(&self.chat.for_version(version))?).await
+    }
+}
+
+/// Dimensions were numbered until 1.16 turned them into identifiers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dimension {
+    Legacy(i32),
+    Named(String)
+}
+
+impl Default for Dimension {
+    fn default() -> Self {
+        Dimension::Legacy(0)
+    }
+}
+
+impl Dimension {
+    fn id(&self) -> i32 {
+        match self {
+            Dimension::Legacy(id) => *id,
+            Dimension::Named(name) => match name.as_ref() {
+                "minecraft:the_nether" => -1,
+                "minecraft:the_end" => 1,
+                _ => 0
+            }
+        }
+    }
+
+    /// Any dimension other than this one, for forcing the client to throw away its world.
+    pub fn other(&self) -> Dimension {
+        match self {
+            Dimension::Legacy(id) => Dimension::Legacy(if *id == 0 { -1 } else { 0 }),
+            Dimension::Named(name) => Dimension::Named(String::from(if name == "minecraft:overworld" {
+                "minecraft:the_nether"
+            } else {
+                "minecraft:overworld"
+            }))
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Dimension::Named(name) => name.to_owned(),
+            Dimension::Legacy(id) => String::from(match id {
+                -1 => "minecraft:the_nether",
+                1 => "minecraft:the_end",
+                _ => "minecraft:overworld"
+            })
+        }
+    }
+}
+
+/// Which fields are actually sent depends on the version, the rest are left at their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct JoinGame {
+    pub entity_id: i32,
+    pub gamemode: u8,
+    /// 1.16+
+    pub previous_gamemode: u8,
+    /// 1.16+
+    pub worlds: Vec<String>,
+    /// 1.16+, kept as the raw NBT compound
+    pub dimension_codec: Vec<u8>,
+    pub dimension: Dimension,
+    /// 1.16+
+    pub world_name: String,
+    /// before 1.14
+    pub difficulty: u8,
+    /// 1.15+
+    pub hashed_seed: i64,
+    pub max_players: u8,
+    /// before 1.16
+    pub level_type: String,
+    /// 1.14+
+    pub view_distance: i32,
+    pub reduced_debug_info: bool,
+    /// 1.15+
+    pub enable_respawn_screen: bool,
+    /// 1.16+
+    pub is_debug: bool,
+    /// 1.16+
+    pub is_flat: bool
+}
+
+impl JoinGame {
+    /// The respawn that puts a client into the world this packet describes.
+    pub fn respawn(&self) -> Respawn {
+        Respawn {
+            dimension: self.dimension.clone(),
+            world_name: self.world_name.to_owned(),
+            difficulty: self.difficulty,
+            hashed_seed: self.hashed_seed,
+            gamemode: self.gamemode,
+            previous_gamemode: self.previous_gamemode,
+            level_type: self.level_type.to_owned(),
+            is_debug: self.is_debug,
+            is_flat: self.is_flat,
+            copy_metadata: false
+        }
+    }
+}
+
+impl Packet for JoinGame {
+    const KIND: Kind = Kind::JoinGame;
+}
+
+#[async_trait]
+impl In for JoinGame {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        let mut packet = JoinGame {
+            entity_id: buffer.read_i32().await?,
+            gamemode: buffer.read_u8().await?,
+            ..Default::default()
+        };
+
+        if version >= version::V1_16 {
+            packet.previous_gamemode = buffer.read_u8().await?;
+
+            let count = buffer.read_varint().await?;
+            for _ in 0..count {
+                packet.worlds.push(buffer.read_string().await?);
+            }
+
+            packet.dimension_codec = nbt::read_raw(buffer).await?;
+            packet.dimension = Dimension::Named(buffer.read_string().await?);
+            packet.world_name = buffer.read_string().await?;
+        } else if version >= version::V1_9_1 {
+            packet.dimension = Dimension::Legacy(buffer.read_i32().await?);
+        } else {
+            packet.dimension = Dimension::Legacy(buffer.read_i8().await? as i32);
+        }
+
+        if version < version::V1_14 {
+            packet.difficulty = buffer.read_u8().await?;
+        }
+
+        if version >= version::V1_15 {
+            packet.hashed_seed = buffer.read_long().await?;
+        }
+
+        packet.max_players = buffer.read_u8().await?;
+
+        if version < version::V1_16 {
+            packet.level_type = buffer.read_string().await?;
+        }
+
+        if version >= version::V1_14 {
+            packet.view_distance = buffer.read_varint().await?;
+        }
+
+        packet.reduced_debug_info = buffer.read_bool().await?;
+
+        if version >= version::V1_15 {
+            packet.enable_respawn_screen = buffer.read_bool().await?;
+        }
+
+        if version >= version::V1_16 {
+            packet.is_debug = buffer.read_bool().await?;
+            packet.is_flat = buffer.read_bool().await?;
+        }
+
+        Ok(packet)
+    }
+}
+
+#[async_trait]
+impl Out for JoinGame {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        buffer.write_i32(self.entity_id).await?;
+        buffer.write_u8(self.gamemode).await?;
+
+        if version >= version::V1_16 {
+            buffer.write_u8(self.previous_gamemode).await?;
+
+            buffer.write_varint(self.worlds.len() as i32).await?;
+            for world in self.worlds {
+                buffer.write_string(world).await?;
+            }
+
+            buffer.write_all(&self.dimension_codec).await?;
+            buffer.write_string(self.dimension.name()).await?;
+            buffer.write_string(self.world_name).await?;
+        } else if version >= version::V1_9_1 {
+            buffer.write_i32(self.dimension.id()).await?;
+        } else {
+            buffer.write_i8(self.dimension.id() as i8).await?;
+        }
+
+        if version < version::V1_14 {
+            buffer.write_u8(self.difficulty).await?;
+        }
+
+        if version >= version::V1_15 {
+            buffer.write_long(self.hashed_seed).await?;
+        }
+
+        buffer.write_u8(self.max_players).await?;
+
+        if version < version::V1_16 {
+            buffer.write_string(self.level_type).await?;
+        }
+
+        if version >= version::V1_14 {
+            buffer.write_varint(self.view_distance).await?;
+        }
+
+        buffer.write_bool(self.reduced_debug_info).await?;
+
+        if version >= version::V1_15 {
+            buffer.write_bool(self.enable_respawn_screen).await?;
+        }
+
+        if version >= version::V1_16 {
+            buffer.write_bool(self.is_debug).await?;
+            buffer.write_bool(self.is_flat).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Which fields are actually sent depends on the version, the rest are left at their defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Respawn {
+    pub dimension: Dimension,
+    /// 1.16+
+    pub world_name: String,
+    /// before 1.14
+    pub difficulty: u8,
+    /// 1.15+
+    pub hashed_seed: i64,
+    pub gamemode: u8,
+    /// 1.16+
+    pub previous_gamemode: u8,
+    /// before 1.16
+    pub level_type: String,
+    /// 1.16+
+    pub is_debug: bool,
+    /// 1.16+
+    pub is_flat: bool,
+    /// 1.16+
+    pub copy_metadata: bool
+}
+
+impl Packet for Respawn {
+    const KIND: Kind = Kind::Respawn;
+}
+
+#[async_trait]
+impl In for Respawn {
+    async fn read<R: AsyncPacketReadExt + std::marker::Unpin + Send + Sync>(buffer: &mut R, version: i32) -> std::io::Result<Self> where Self: Sized {
+        let mut packet = Respawn::default();
+
+        if version >= version::V1_16 {
+            packet.dimension = Dimension::Named(buffer.read_string().await?);
+            packet.world_name = buffer.read_string().await?;
+            packet.hashed_seed = buffer.read_long().await?;
+            packet.gamemode = buffer.read_u8().await?;
+            packet.previous_gamemode = buffer.read_u8().await?;
+            packet.is_debug = buffer.read_bool().await?;
+            packet.is_flat = buffer.read_bool().await?;
+            packet.copy_metadata = buffer.read_bool().await?;
+
+            return Ok(packet);
+        }
+
+        packet.dimension = Dimension::Legacy(buffer.read_i32().await?);
+
+        if version < version::V1_14 {
+            packet.difficulty = buffer.read_u8().await?;
+        }
+
+        if version >= version::V1_15 {
+            packet.hashed_seed = buffer.read_long().await?;
+        }
+
+        packet.gamemode = buffer.read_u8().await?;
+        packet.level_type = buffer.read_string().await?;
+
+        Ok(packet)
+    }
+}
+
+#[async_trait]
+impl Out for Respawn {
+    async fn write<W: AsyncPacketWriteExt + std::marker::Unpin + Send + Sync>(self, buffer: &mut W, version: i32) -> std::io::Result<()> {
+        if version >= version::V1_16 {
+            buffer.write_string(self.dimension.name()).await?;
+            buffer.write_string(self.world_name).await?;
+            buffer.write_long(self.hashed_seed).await?;
+            buffer.write_u8(self.gamemode).await?;
+            buffer.write_u8(self.previous_gamemode).await?;
+            buffer.write_bool(self.is_debug).await?;
+            buffer.write_bool(self.is_flat).await?;
+            buffer.write_bool(self.copy_metadata).await?;
+
+            return Ok(());
+        }
+
+        buffer.write_i32(self.dimension.id()).await?;
+
+        if version < version::V1_14 {
+            buffer.write_u8(self.difficulty).await?;
+        }
+
+        if version >= version::V1_15 {
+            buffer.write_long(self.hashed_seed).await?;
+        }
+
+        buffer.write_u8(self.gamemode).await?;
+        buffer.write_string(self.level_type).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::frame::RawPacket;
+    use crate::packet::registry;
+    use tokio::io::AsyncWriteExt;
+
+    fn argument(name: &str, parser: &str, properties: Vec<u8>) -> CommandNode {
+        CommandNode {
+            flags: NODE_ARGUMENT,
+            children: Vec::new(),
+            redirect: None,
+            name: Some(name.to_owned()),
+            parser: Some((parser.to_owned(), properties)),
+            suggestions: None
+        }
+    }
+
+    #[tokio::test]
+    async fn declared_commands_round_trip() {
+        let mut bounded = vec![0x03];
+        bounded.extend_from_slice(&1i32.to_be_bytes());
+        bounded.extend_from_slice(&64i32.to_be_bytes());
+
+        let commands = DeclareCommands {
+            nodes: vec![
+                CommandNode {
+                    flags: NODE_ROOT,
+                    children: vec![1, 5],
+                    redirect: None,
+                    name: None,
+                    parser: None,
+                    suggestions: None
+                },
+                CommandNode::literal("give", vec![2]),
+                argument("targets", "minecraft:entity", vec![0x00]),
+                argument("count", "brigadier:integer", bounded),
+                argument("silent", "brigadier:bool", Vec::new()),
+                CommandNode {
+                    flags: NODE_LITERAL | NODE_REDIRECT,
+                    redirect: Some(1),
+                    ..CommandNode::literal("g", Vec::new())
+                },
+                CommandNode::greedy_argument("message")
+            ],
+            root: 0
+        };
+
+        let packet = RawPacket::encode(commands.clone(), version::V1_16).await.unwrap();
+        assert_eq!(packet.decode::<DeclareCommands>(version::V1_16).await.unwrap(), commands);
+    }
+
+    #[tokio::test]
+    async fn refuses_parsers_it_can_not_read_past() {
+        let mut payload = Vec::new();
+        payload.write_varint(1).await.unwrap();
+        payload.write_u8(NODE_ARGUMENT).await.unwrap();
+        payload.write_varint(0).await.unwrap();
+        payload.write_string(String::from("amount")).await.unwrap();
+        payload.write_string(String::from("somemod:fluid")).await.unwrap();
+        payload.write_u8(0x01).await.unwrap();
+        payload.write_varint(0).await.unwrap();
+
+        let packet = RawPacket::new(registry::id_of(Kind::DeclareCommands, version::V1_16).unwrap(), payload.into());
+        assert!(packet.decode::<DeclareCommands>(version::V1_16).await.is_err());
+    }
+}
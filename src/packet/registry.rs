@@ -0,0 +1,174 @@
+use crate::packet::version::*;
+
+/// The protocol version to look packets up with before the handshake has told us which version
+/// the client speaks. Handshake, status and login packets are mapped from here on.
+pub const ANY_VERSION: i32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    Handshake,
+    Status,
+    Login,
+    Play
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Serverbound,
+    Clientbound
+}
+
+/// Every packet rift knows how to decode or encode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Handshake,
+    StatusRequest,
+    StatusResponse,
+    Ping,
+    LoginStart,
+    EncryptionRequest,
+    EncryptionResponse,
+    LoginSuccess,
+    SetCompression,
+    LoginDisconnect,
+    LoginPluginRequest,
+    LoginPluginResponse,
+    ClientboundKeepAlive,
+    ServerboundKeepAlive,
+    ClientboundChat,
+    ServerboundChat,
+    ClientboundPluginMessage,
+    ServerboundPluginMessage,
+    JoinGame,
+    Respawn,
+    PlayDisconnect,
+    TabCompleteRequest,
+    TabCompleteResponse,
+    DeclareCommands,
+    /// Any packet passed along without being decoded, see `RawPacket`.
+    Raw
+}
+
+struct Mapping {
+    kind: Kind,
+    state: State,
+    direction: Direction,
+    // (first protocol version, id) pairs, sorted by version
+    ids: &'static [(i32, i32)]
+}
+
+const MAPPINGS: &[Mapping] = &[
+    Mapping { kind: Kind::Handshake, state: State::Handshake, direction: Direction::Serverbound, ids: &[(ANY_VERSION, 0x00)] },
+
+    Mapping { kind: Kind::StatusRequest, state: State::Status, direction: Direction::Serverbound, ids: &[(ANY_VERSION, 0x00)] },
+    Mapping { kind: Kind::Ping, state: State::Status, direction: Direction::Serverbound, ids: &[(ANY_VERSION, 0x01)] },
+    Mapping { kind: Kind::StatusResponse, state: State::Status, direction: Direction::Clientbound, ids: &[(ANY_VERSION, 0x00)] },
+    Mapping { kind: Kind::Ping, state: State::Status, direction: Direction::Clientbound, ids: &[(ANY_VERSION, 0x01)] },
+
+    Mapping { kind: Kind::LoginStart, state: State::Login, direction: Direction::Serverbound, ids: &[(ANY_VERSION, 0x00)] },
+    Mapping { kind: Kind::EncryptionResponse, state: State::Login, direction: Direction::Serverbound, ids: &[(ANY_VERSION, 0x01)] },
+    Mapping { kind: Kind::LoginDisconnect, state: State::Login, direction: Direction::Clientbound, ids: &[(ANY_VERSION, 0x00)] },
+    Mapping { kind: Kind::EncryptionRequest, state: State::Login, direction: Direction::Clientbound, ids: &[(ANY_VERSION, 0x01)] },
+    Mapping { kind: Kind::LoginSuccess, state: State::Login, direction: Direction::Clientbound, ids: &[(ANY_VERSION, 0x02)] },
+    Mapping { kind: Kind::SetCompression, state: State::Login, direction: Direction::Clientbound, ids: &[(ANY_VERSION, 0x03)] },
+    Mapping { kind: Kind::LoginPluginRequest, state: State::Login, direction: Direction::Clientbound, ids: &[(V1_13, 0x04)] },
+    Mapping { kind: Kind::LoginPluginResponse, state: State::Login, direction: Direction::Serverbound, ids: &[(V1_13, 0x02)] },
+
+    Mapping { kind: Kind::ServerboundKeepAlive, state: State::Play, direction: Direction::Serverbound, ids: &[(V1_8, 0x00), (V1_9, 0x0B), (V1_12, 0x0C), (V1_12_1, 0x0B), (V1_13, 0x0E), (V1_14, 0x0F), (V1_16, 0x10)] },
+    Mapping { kind: Kind::ServerboundChat, state: State::Play, direction: Direction::Serverbound, ids: &[(V1_8, 0x01), (V1_9, 0x02), (V1_12, 0x03), (V1_12_1, 0x02), (V1_14, 0x03)] },
+    Mapping { kind: Kind::TabCompleteRequest, state: State::Play, direction: Direction::Serverbound, ids: &[(V1_8, 0x14), (V1_9, 0x01), (V1_12, 0x02), (V1_12_1, 0x01), (V1_13, 0x05), (V1_14, 0x06)] },
+    Mapping { kind: Kind::ServerboundPluginMessage, state: State::Play, direction: Direction::Serverbound, ids: &[(V1_8, 0x17), (V1_9, 0x09), (V1_12, 0x0A), (V1_12_1, 0x09), (V1_13, 0x0A), (V1_14, 0x0B)] },
+    Mapping { kind: Kind::ClientboundKeepAlive, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x00), (V1_9, 0x1F), (V1_13, 0x21), (V1_14, 0x20), (V1_15, 0x21), (V1_16, 0x20)] },
+    Mapping { kind: Kind::JoinGame, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x01), (V1_9, 0x23), (V1_13, 0x25), (V1_15, 0x26), (V1_16, 0x25)] },
+    Mapping { kind: Kind::ClientboundChat, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x02), (V1_9, 0x0F), (V1_13, 0x0E), (V1_15, 0x0F), (V1_16, 0x0E)] },
+    Mapping { kind: Kind::Respawn, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x07), (V1_9, 0x33), (V1_12, 0x34), (V1_12_1, 0x35), (V1_13, 0x38), (V1_14, 0x3A), (V1_15, 0x3B), (V1_16, 0x3A)] },
+    Mapping { kind: Kind::PlayDisconnect, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x40), (V1_9, 0x1A), (V1_13, 0x1B), (V1_14, 0x1A), (V1_15, 0x1B), (V1_16, 0x1A)] },
+    Mapping { kind: Kind::TabCompleteResponse, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x3A), (V1_9, 0x0E), (V1_13, 0x10), (V1_15, 0x11), (V1_16, 0x10)] },
+    Mapping { kind: Kind::ClientboundPluginMessage, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_8, 0x3F), (V1_9, 0x18), (V1_13, 0x19), (V1_14, 0x18), (V1_15, 0x19), (V1_16, 0x18)] },
+    Mapping { kind: Kind::DeclareCommands, state: State::Play, direction: Direction::Clientbound, ids: &[(V1_13, 0x11), (V1_15, 0x12), (V1_16, 0x11)] }
+];
+
+impl Mapping {
+    fn id(&self, version: i32) -> Option<i32> {
+        self.ids.iter()
+            .rev()
+            .find(|(since, _)| *since <= version)
+            .map(|(_, id)| *id)
+    }
+}
+
+/// Resolves the id `kind` is sent with under protocol `version`, if that version has it at all.
+pub fn id_of(kind: Kind, version: i32) -> Option<i32> {
+    MAPPINGS.iter()
+        .find(|mapping| mapping.kind == kind)
+        .and_then(|mapping| mapping.id(version))
+}
+
+/// Resolves which packet a frame with `id` is. `None` means rift doesn't know the packet, which
+/// is only an error outside of the play state, where unknown packets are passed through untouched.
+pub fn lookup(state: State, direction: Direction, version: i32, id: i32) -> Option<Kind> {
+    MAPPINGS.iter()
+        .filter(|mapping| mapping.state == state && mapping.direction == direction)
+        .find(|mapping| mapping.id(version) == Some(id))
+        .map(|mapping| mapping.kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every version some id in `MAPPINGS` changes at, which is all that needs checking.
+    fn boundaries() -> Vec<i32> {
+        let mut versions: Vec<i32> = MAPPINGS.iter()
+            .flat_map(|mapping| mapping.ids.iter().map(|(since, _)| *since))
+            .chain(vec![MINIMUM, MAXIMUM])
+            .filter(|version| is_supported(*version))
+            .collect();
+
+        versions.sort();
+        versions.dedup();
+        versions
+    }
+
+    #[test]
+    fn picks_the_id_of_the_latest_change() {
+        assert_eq!(id_of(Kind::ClientboundChat, V1_8), Some(0x02));
+        assert_eq!(id_of(Kind::ClientboundChat, V1_12_2), Some(0x0F));
+        assert_eq!(id_of(Kind::ClientboundChat, V1_13), Some(0x0E));
+        assert_eq!(id_of(Kind::ClientboundChat, V1_16_1), Some(0x0E));
+
+        assert_eq!(id_of(Kind::ServerboundChat, V1_12), Some(0x03));
+        assert_eq!(id_of(Kind::ServerboundChat, V1_12_1), Some(0x02));
+        assert_eq!(id_of(Kind::JoinGame, V1_15), Some(0x26));
+    }
+
+    #[test]
+    fn has_no_id_before_a_packet_exists() {
+        assert_eq!(id_of(Kind::LoginPluginRequest, V1_12_2), None);
+        assert_eq!(id_of(Kind::LoginPluginRequest, V1_13), Some(0x04));
+    }
+
+    #[test]
+    fn looks_up_what_it_maps() {
+        for version in boundaries() {
+            for mapping in MAPPINGS {
+                if let Some(id) = mapping.id(version) {
+                    assert_eq!(lookup(mapping.state, mapping.direction, version, id), Some(mapping.kind), "{:?} in {}", mapping.kind, version);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn never_maps_two_packets_to_one_id() {
+        for version in boundaries() {
+            for (index, mapping) in MAPPINGS.iter().enumerate() {
+                for other in &MAPPINGS[index + 1..] {
+                    if mapping.state == other.state && mapping.direction == other.direction {
+                        assert!(mapping.id(version).is_none() || mapping.id(version) != other.id(version), "{:?} and {:?} in {}", mapping.kind, other.kind, version);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Protocol version numbers of the releases where something rift understands changed.
+
+pub const V1_8: i32 = 47;
+pub const V1_9: i32 = 107;
+pub const V1_9_1: i32 = 108;
+pub const V1_12: i32 = 335;
+pub const V1_12_1: i32 = 338;
+pub const V1_12_2: i32 = 340;
+pub const V1_13: i32 = 393;
+pub const V1_14: i32 = 477;
+pub const V1_15: i32 = 573;
+pub const V1_16: i32 = 735;
+pub const V1_16_1: i32 = 736;
+
+pub const MINIMUM: i32 = V1_8;
+pub const MAXIMUM: i32 = V1_16_1;
+
+/// The supported range as players know it.
+pub const SUPPORTED_RANGE: &str = "1.8-1.16.1";
+
+pub fn is_supported(version: i32) -> bool {
+    (MINIMUM..=MAXIMUM).contains(&version)
+}
@@ -1,7 +1,14 @@
 use crate::command::CommandSender;
+use crate::packet::Chat;
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use openssl::hash::MessageDigest;
+use tokio::sync::mpsc::{Sender, error::TrySendError};
+use crate::protocol::relay::Request;
 
 #[derive(Clone, Serialize, Debug, Deserialize)]
 pub struct Player {
@@ -9,16 +16,199 @@ pub struct Player {
     pub name: String,
     pub properties: Vec<HashMap<String, String>>,
     #[serde(skip_serializing)]
-    pub server: Option<String>
+    pub server: Option<String>,
+    /// Where the player is connecting from.
+    #[serde(skip)]
+    pub address: Option<SocketAddr>,
+    /// Where requests for the player's relay go, `None` until the player is relayed to a backend.
+    #[serde(skip)]
+    pub(crate) relay: Option<Sender<Request>>
 }
 
 impl Player {
     pub fn new<S: Into<String>>(id: Uuid, name: S) -> Self {
         Player {
-            id: id,
+            id,
             name: name.into(),
             properties: Vec::new(),
-            server: None
+            server: None,
+            address: None,
+            relay: None
+        }
+    }
+
+    /// A player the session server never vouched for, with the UUID vanilla servers give players
+    /// in offline mode: a version 3 UUID made from the MD5 of `OfflinePlayer:<name>`.
+    pub fn offline<S: Into<String>>(name: S) -> Self {
+        let name = name.into();
+
+        let digest = openssl::hash::hash(MessageDigest::md5(), format!("OfflinePlayer:{}", name).as_bytes())
+            .expect("MD5 is always available");
+
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&digest);
+        bytes[6] = (bytes[6] & 0x0f) | 0x30;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        Player::new(Uuid::from_bytes(bytes), name)
+    }
+
+    /// Hands `request` to the player's relay. Fails rather than waits when the relay has too many
+    /// requests queued up already.
+    pub(crate) fn request(&self, request: Request) -> std::io::Result<()> {
+        let not_connected = || std::io::Error::new(std::io::ErrorKind::NotConnected, format!("{} isn't connected to a server.", self.name));
+
+        match self.relay.clone().ok_or_else(not_connected)?.try_send(request) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, format!("{}'s connection is too busy to take requests.", self.name))),
+            Err(TrySendError::Closed(_)) => Err(not_connected())
+        }
+    }
+}
+
+/// Everyone connected to the proxy, shared between the connections that come and go and anything
+/// that wants to know who's online.
+#[derive(Default)]
+pub struct PlayerList {
+    entries: RwLock<Entries>,
+    sessions: AtomicU64
+}
+
+#[derive(Default)]
+struct Entries {
+    by_id: HashMap<Uuid, Entry>,
+    /// Lowercase names, the way players type them.
+    by_name: HashMap<String, Uuid>
+}
+
+struct Entry {
+    player: Player,
+    /// Tells a player's connection apart from a later one with the same UUID.
+    session: u64
+}
+
+impl PlayerList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Player> {
+        self.entries.read().unwrap().by_id.get(id)
+            .map(|entry| entry.player.clone())
+    }
+
+    /// Looks a player up by name, ignoring case.
+    pub fn get_by_name(&self, name: &str) -> Option<Player> {
+        let entries = self.entries.read().unwrap();
+        entries.by_name.get(&name.to_lowercase())
+            .and_then(|id| entries.by_id.get(id))
+            .map(|entry| entry.player.clone())
+    }
+
+    pub fn all(&self) -> Vec<Player> {
+        self.entries.read().unwrap().by_id.values()
+            .map(|entry| entry.player.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds `player` unless that takes the list past `capacity` players or, when `replace` is off,
+    /// they're already connected. Anyone already there with the same UUID or name is taken off the
+    /// list and handed back to be kicked, and doesn't count towards `capacity`.
+    ///
+    /// All of that happens under one lock, so logins racing each other can't both squeeze into the
+    /// last slot or both miss each other. The player stays listed until the returned
+    /// `Registration` is dropped.
+    pub(crate) fn admit(self: &Arc<Self>, player: Player, capacity: Option<usize>, replace: bool) -> Result<(Registration, Vec<Player>), Refusal> {
+        let id = player.id;
+        let name = player.name.to_lowercase();
+
+        let mut entries = self.entries.write().unwrap();
+
+        let mut existing: Vec<Uuid> = entries.by_name.get(&name).copied().into_iter().collect();
+        if entries.by_id.contains_key(&id) && !existing.contains(&id) {
+            existing.push(id);
+        }
+
+        if !existing.is_empty() && !replace {
+            return Err(Refusal::AlreadyConnected);
+        }
+
+        if capacity.is_some_and(|capacity| entries.by_id.len() - existing.len() >= capacity) {
+            return Err(Refusal::Full);
+        }
+
+        let replaced = existing.iter()
+            .filter_map(|old| entries.by_id.remove(old))
+            .map(|old| old.player)
+            .collect::<Vec<_>>();
+        for old in &replaced {
+            entries.by_name.remove(&old.name.to_lowercase());
+        }
+
+        let session = self.sessions.fetch_add(1, Ordering::Relaxed);
+        entries.by_name.insert(name, id);
+        entries.by_id.insert(id, Entry {
+            player,
+            session
+        });
+
+        Ok((Registration {
+            list: self.clone(),
+            id,
+            session
+        }, replaced))
+    }
+}
+
+/// Why `PlayerList::admit` turned a player away.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Refusal {
+    Full,
+    AlreadyConnected
+}
+
+/// A player's place in the `PlayerList`, given up when dropped.
+pub struct Registration {
+    list: Arc<PlayerList>,
+    id: Uuid,
+    session: u64
+}
+
+impl Registration {
+    /// Brings the listed copy of the player up to date, e.g. after they've switched servers.
+    /// Returns whether they're still listed at all, a later login may have taken their place.
+    pub(crate) fn update(&self, player: &Player) -> bool {
+        let mut entries = self.list.entries.write().unwrap();
+        match entries.by_id.get_mut(&self.id) {
+            Some(entry) if entry.session == self.session => {
+                entry.player = player.clone();
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut entries = self.list.entries.write().unwrap();
+
+        // whoever took our place since isn't ours to remove
+        let ours = entries.by_id.get(&self.id)
+            .is_some_and(|entry| entry.session == self.session);
+
+        if ours {
+            if let Some(entry) = entries.by_id.remove(&self.id) {
+                entries.by_name.remove(&entry.player.name.to_lowercase());
+            }
         }
     }
 }
@@ -28,7 +218,85 @@ impl CommandSender for Player {
         &self.name
     }
  
-    fn send_message(&self, message: String) {
-        // todo: send msg
+    /// Shows the player `message` in chat. Messages to a player that isn't relayed to a server
+    /// yet, or any longer, go nowhere.
+    fn send_message(&self, message: Chat) {
+        let _ = self.request(Request::Message(Box::new(message)));
+    }
+
+    fn as_player(&self) -> Option<&Player> {
+        Some(self)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_players_get_the_uuid_vanilla_gives_them() {
+        let player = Player::offline("Notch");
+
+        assert_eq!(player.id, Uuid::parse_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap());
+        assert_eq!(player.id.get_version_num(), 3);
+        assert_eq!(player.name, "Notch");
     }
-}
\ No newline at end of file
+
+    fn players(names: &[&str]) -> (Arc<PlayerList>, Vec<Registration>) {
+        let list = Arc::new(PlayerList::new());
+        let registrations = names.iter()
+            .map(|name| list.admit(Player::offline(*name), None, true).unwrap().0)
+            .collect();
+
+        (list, registrations)
+    }
+
+    #[test]
+    fn turns_players_away_once_full() {
+        let (list, _registrations) = players(&["Notch", "jeb_"]);
+
+        assert_eq!(list.admit(Player::offline("Dinnerbone"), Some(2), true).err(), Some(Refusal::Full));
+        assert!(list.admit(Player::offline("Dinnerbone"), Some(3), true).is_ok());
+    }
+
+    #[test]
+    fn replacing_someone_makes_room_for_them() {
+        let (list, _registrations) = players(&["Notch", "jeb_"]);
+
+        let (_registration, replaced) = list.admit(Player::offline("Notch"), Some(2), true).unwrap();
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn hands_back_everyone_replaced_by_uuid_or_name() {
+        let (list, _registrations) = players(&["Notch", "jeb_"]);
+
+        // jeb_'s UUID under Notch's name, in a different case
+        let player = Player::new(Player::offline("jeb_").id, "NOTCH");
+        let (_registration, replaced) = list.admit(player, None, true).unwrap();
+
+        let mut names: Vec<String> = replaced.into_iter().map(|player| player.name).collect();
+        names.sort();
+        assert_eq!(names, ["Notch", "jeb_"]);
+        assert_eq!(list.len(), 1);
+        assert_eq!(list.get_by_name("notch").unwrap().name, "NOTCH");
+    }
+
+    #[test]
+    fn keeps_the_first_login_when_not_replacing() {
+        let (list, _registrations) = players(&["Notch"]);
+
+        assert_eq!(list.admit(Player::offline("notch"), None, false).err(), Some(Refusal::AlreadyConnected));
+        assert_eq!(list.get_by_name("Notch").unwrap().name, "Notch");
+    }
+
+    #[test]
+    fn registrations_only_remove_their_own_session() {
+        let (list, mut registrations) = players(&["Notch"]);
+        let (_replacement, _) = list.admit(Player::offline("Notch"), None, true).unwrap();
+
+        assert!(!registrations[0].update(&Player::offline("Notch")));
+        registrations.clear();
+        assert!(list.get_by_name("Notch").is_some());
+    }
+}
@@ -0,0 +1,123 @@
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use crate::config::ProxyConfig;
+use crate::player::Player;
+
+/// Checks with a session server that a player logging in really is who they say they are.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// Asks whether `name` joined a server identified by `server_hash`, returning their profile
+    /// if so and `None` if the session server doesn't know about it. `ip` is passed along when
+    /// the session server should check the player is connecting from where they joined.
+    async fn has_joined(&self, name: &str, server_hash: &str, ip: Option<IpAddr>) -> io::Result<Option<Player>>;
+}
+
+/// Authenticates against Mojang's session server, or anything that speaks its API.
+pub struct MojangAuthenticator {
+    client: Client,
+    base_url: String,
+    prevent_proxy_connections: bool
+}
+
+impl MojangAuthenticator {
+    pub fn new(config: &ProxyConfig) -> io::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(config.session_timeout))
+            .build()
+            .map_err(to_io)?;
+
+        Ok(MojangAuthenticator {
+            client,
+            base_url: config.session_server.trim_end_matches('/').to_owned(),
+            prevent_proxy_connections: config.prevent_proxy_connections
+        })
+    }
+}
+
+#[async_trait]
+impl Authenticator for MojangAuthenticator {
+    async fn has_joined(&self, name: &str, server_hash: &str, ip: Option<IpAddr>) -> io::Result<Option<Player>> {
+        let mut query = vec![("username", name.to_owned()), ("serverId", server_hash.to_owned())];
+        if let (true, Some(ip)) = (self.prevent_proxy_connections, ip) {
+            query.push(("ip", ip.to_string()));
+        }
+
+        let response = self.client.get(&format!("{}/session/minecraft/hasJoined", self.base_url))
+            .query(&query)
+            .send()
+            .await
+            .map_err(to_io)?;
+
+        // anything but a profile, 204 being the usual, means the player never joined
+        if response.status() != StatusCode::OK {
+            return Ok(None);
+        }
+
+        Ok(Some(response.json::<Player>().await.map_err(to_io)?))
+    }
+}
+
+fn to_io(error: reqwest::Error) -> io::Error {
+    let kind = if error.is_timeout() {
+        io::ErrorKind::TimedOut
+    } else {
+        io::ErrorKind::Other
+    };
+
+    io::Error::new(kind, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// Answers one request with `response`, handing back the request line it was sent.
+    async fn session_server(response: &'static str) -> (MojangAuthenticator, JoinHandle<String>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let authenticator = MojangAuthenticator {
+            client: Client::new(),
+            base_url: format!("http://{}", listener.local_addr().unwrap()),
+            prevent_proxy_connections: false
+        };
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap().lines().next().unwrap().to_owned()
+        });
+
+        (authenticator, server)
+    }
+
+    #[tokio::test]
+    async fn no_content_means_the_player_never_joined() {
+        let (authenticator, server) = session_server("HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n").await;
+
+        assert!(authenticator.has_joined("Notch", "-1234abcd", None).await.unwrap().is_none());
+        assert_eq!(server.await.unwrap(), "GET /session/minecraft/hasJoined?username=Notch&serverId=-1234abcd HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn reads_the_profile_of_players_who_joined() {
+        let (authenticator, _server) = session_server("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 72\r\nConnection: close\r\n\r\n\
+            {\"id\":\"069a79f444e94726a5befca90e38aaf5\",\"name\":\"Notch\",\"properties\":[]}").await;
+
+        let player = authenticator.has_joined("Notch", "-1234abcd", None).await.unwrap().unwrap();
+        assert_eq!(player.name, "Notch");
+        assert_eq!(player.id.to_simple().to_string(), "069a79f444e94726a5befca90e38aaf5");
+    }
+}
@@ -0,0 +1,225 @@
+use std::{fmt, io};
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use log::{error, trace};
+use crate::config::{ProxyConfig, ServerConfig, Forwarding};
+use crate::packet::{AsyncPacketReadExt, AsyncPacketWriteExt, Chat};
+use crate::packet::{handshake, login};
+use crate::packet::compression::CompressedStream;
+use crate::packet::registry::{self, State, Direction, Kind};
+use crate::player::Player;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+const PLAYER_INFO_CHANNEL: &str = "velocity:player_info";
+const PLAYER_INFO_VERSION: i32 = 1;
+/// What Forge clients append to the handshake host, for 1.7 to 1.12, 1.13 to 1.17 and 1.18 onwards.
+const FML_MARKERS: [&str; 3] = ["\0FML\0", "\0FML2\0", "\0FML3\0"];
+
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The backend turned the player away with this reason.
+    Kicked(Box<Chat>),
+    Io(io::Error)
+}
+
+impl From<io::Error> for ConnectError {
+    fn from(error: io::Error) -> Self {
+        ConnectError::Io(error)
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Kicked(chat) => write!(f, "kicked: {}", chat),
+            ConnectError::Io(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+/// Opens a connection to `server` and logs `player` in with the client's `handshake`, returning
+/// the connection once the backend has switched to the play state.
+pub async fn connect(config: &ProxyConfig, server: &ServerConfig, handshake: &handshake::Packet, player: &Player) -> Result<CompressedStream<TcpStream>, ConnectError> {
+    let version = handshake.version;
+
+    let stream = TcpStream::connect(server.address).await?;
+    stream.set_nodelay(true)?;
+
+    trace!("Established proxy connection to {} ({}) for {}.", server.id, server.address, player.name);
+
+    let mut handshake = handshake.clone();
+    if config.ip_forward == Forwarding::Legacy {
+        handshake.address = forwarded_address(&handshake.address, player);
+    }
+
+    let mut stream = CompressedStream::new(stream);
+    stream.write_packet(handshake, version).await?;
+    stream.write_packet(login::Start {
+        name: player.name.to_owned()
+    }, version).await?;
+
+    loop {
+        let packet = stream.read_frame().await?;
+
+        match registry::lookup(State::Login, Direction::Clientbound, version, packet.id) {
+            Some(Kind::LoginDisconnect) => {
+                let chat = packet.decode::<login::Disconnect>(version).await?.chat;
+
+                if let Some(key) = chat.translation_key() {
+                    if key.contains("Connection throttled") {
+                        error!("Connection throttle is enabled for {}. Turn it off!", server.id);
+                    }
+                }
+
+                return Err(ConnectError::Kicked(Box::new(chat)));
+            },
+
+            Some(Kind::LoginSuccess) => {
+                packet.decode::<login::Success>(version).await?;
+                return Ok(stream);
+            },
+
+            Some(Kind::LoginPluginRequest) => {
+                let request = packet.decode::<login::PluginRequest>(version).await?;
+
+                let response = if request.channel == PLAYER_INFO_CHANNEL && config.ip_forward == Forwarding::Modern {
+                    login::PluginResponse {
+                        message_id: request.message_id,
+                        successful: true,
+                        data: player_info(config, player).await?
+                    }
+                } else {
+                    login::PluginResponse {
+                        message_id: request.message_id,
+                        successful: false,
+                        data: Vec::new()
+                    }
+                };
+
+                stream.write_packet(response, version).await?;
+            },
+
+            Some(Kind::SetCompression) => {
+                let compression = packet.decode::<login::SetCompression>(version).await?;
+                stream.set_threshold(compression.threshold);
+            },
+
+            _ => return Err(ConnectError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected packet {:#04x} during login.", packet.id))))
+        }
+    }
+}
+
+/// Packs what the backend can't know on its own into the handshake address the way BungeeCord
+/// does (`host\0clientIp\0uuid\0propertiesJson`), which servers running with `bungeecord: true`
+/// pick apart again.
+///
+/// The host is the client's to choose, so anything it put after a `\0` is cut off rather than
+/// passed on as forwarded data. Forge's marker is the only thing kept, moved into an `extraData`
+/// property the way BungeeCord does it.
+fn forwarded_address(host: &str, player: &Player) -> String {
+    let (host, extra) = match host.find('\0') {
+        Some(end) => (&host[..end], &host[end..]),
+        None => (host, "")
+    };
+
+    let ip = player.address
+        .map(|address| address.ip().to_string())
+        .unwrap_or_default();
+
+    let mut properties = player.properties.clone();
+    if FML_MARKERS.contains(&extra) {
+        let mut property = HashMap::new();
+        property.insert("name".to_owned(), "extraData".to_owned());
+        property.insert("value".to_owned(), extra.replace('\0', "\u{1}"));
+        properties.push(property);
+    }
+
+    let mut address = format!("{}\0{}\0{}", host, ip, player.id.to_simple());
+    if !properties.is_empty() {
+        address.push('\0');
+        address.push_str(&serde_json::to_string(&properties).unwrap_or_default());
+    }
+
+    address
+}
+
+/// Answers Velocity's `velocity:player_info` request: the client's IP, UUID, name and properties,
+/// prefixed with an HMAC-SHA256 signature made with the shared forwarding secret so the backend
+/// knows the info really comes from us.
+async fn player_info(config: &ProxyConfig, player: &Player) -> io::Result<Vec<u8>> {
+    let secret = config.forwarding_secret
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Modern forwarding needs a forwarding_secret."))?;
+
+    let mut info = Vec::new();
+    info.write_varint(PLAYER_INFO_VERSION).await?;
+    info.write_string(player.address.map(|address| address.ip().to_string()).unwrap_or_default()).await?;
+    info.write_uuid(player.id).await?;
+    info.write_string(player.name.to_owned()).await?;
+
+    info.write_varint(player.properties.len() as i32).await?;
+    for property in &player.properties {
+        info.write_string(property.get("name").cloned().unwrap_or_default()).await?;
+        info.write_string(property.get("value").cloned().unwrap_or_default()).await?;
+
+        match property.get("signature") {
+            Some(signature) => {
+                info.write_bool(true).await?;
+                info.write_string(signature.to_owned()).await?;
+            },
+            None => info.write_bool(false).await?
+        }
+    }
+
+    let key = PKey::hmac(secret.as_bytes()).map_err(to_io)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(to_io)?;
+    signer.update(&info).map_err(to_io)?;
+
+    let mut data = signer.sign_to_vec().map_err(to_io)?;
+    data.extend_from_slice(&info);
+
+    Ok(data)
+}
+
+fn to_io(error: openssl::error::ErrorStack) -> io::Error {
+    io::Error::other(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn player() -> Player {
+        let mut player = Player::new(Uuid::parse_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(), "Notch");
+        player.address = Some("203.0.113.7:51234".parse().unwrap());
+        player
+    }
+
+    #[test]
+    fn forwards_the_host_ip_and_uuid() {
+        assert_eq!(forwarded_address("play.example.com", &player()),
+            "play.example.com\x00203.0.113.7\x00069a79f444e94726a5befca90e38aaf5");
+    }
+
+    #[test]
+    fn cuts_off_whatever_the_client_hid_in_the_host() {
+        let forged = "play.example.com\x00198.51.100.1\x00853c80ef3c3749fdaa49938b674adae6\x00[]";
+
+        assert_eq!(forwarded_address(forged, &player()),
+            "play.example.com\x00203.0.113.7\x00069a79f444e94726a5befca90e38aaf5");
+    }
+
+    #[test]
+    fn keeps_the_forge_marker_as_a_property() {
+        let address = forwarded_address("play.example.com\x00FML2\x00", &player());
+        let (forwarded, properties) = address.rsplit_once('\x00').unwrap();
+        let properties: Vec<HashMap<String, String>> = serde_json::from_str(properties).unwrap();
+
+        assert_eq!(forwarded, "play.example.com\x00203.0.113.7\x00069a79f444e94726a5befca90e38aaf5");
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties[0]["name"], "extraData");
+        assert_eq!(properties[0]["value"], "\u{1}FML2\u{1}");
+    }
+}
@@ -1,54 +1,325 @@
-use std::{io, net};
+use std::{fmt, io, net};
+use std::sync::Arc;
 use tokio::net::{TcpStream};
-use crate::packet::{In, AsyncPacketReadExt, AsyncPacketWriteExt};
-use crate::packet::handshake;
-use std::io::{Error, ErrorKind};
-use crate::util::color::Color;
-use crate::player::Player;
-use log::{info, debug, error, trace};
+use crate::packet::{AsyncPacketReadExt, AsyncPacketWriteExt};
+use crate::packet::{handshake, login};
+use crate::player::{Player, PlayerList, Registration, Refusal};
+use log::{debug, trace};
 use rand::Rng;
+use crate::config::{ProxyConfig, Messages, DuplicateLogin};
+use crate::packet::Chat;
+use crate::packet::encryption::EncryptedStream;
+use crate::protocol::auth::Authenticator;
+use crate::protocol::relay::Request;
 
-pub async fn attempt_login<T: crate::server::Server>(config: crate::config::ProxyConfig, server: &T, stream: &mut TcpStream, addr: net::SocketAddr) -> io::Result<(Player, Vec<u8>)> {
-    let req: io::Result<crate::packet::login::Start> = stream.receive().await;
-    if let Ok(packet) = req {
-        debug!("User \"{}\" initiating login process.", packet.name);
-        let token = format!("{}", rand::thread_rng().gen::<i64>());
-        let token_bytes = token.as_bytes();
-
-        let encryption_request = crate::packet::login::EncryptionRequest {
-            id: String::from(""),
-            public_key: server.get_rsa().public_key_to_der().unwrap(),
-            token: token_bytes.to_vec()
+/// Lets a player in even when there are already `max_players` online.
+pub const BYPASS_MAX_PLAYERS: &str = "rift.bypass.max_players";
+
+/// Everything that can keep a client from logging in.
+#[derive(Debug)]
+pub enum LoginError {
+    /// The client didn't send a Login Start.
+    InvalidStart(io::Error),
+    /// The client asked to play offline under a name vanilla wouldn't allow.
+    InvalidName(String),
+    /// The encryption response couldn't be read or decrypted.
+    InvalidEncryptionResponse(io::Error),
+    /// The client sent back a different verify token than it was given.
+    InvalidToken,
+    /// The session server says the player never joined.
+    FailedToVerify,
+    /// The session server couldn't be asked.
+    AuthenticationUnavailable(io::Error),
+    /// There are already `max_players` online.
+    ServerFull,
+    /// The player is already connected and new logins don't replace them.
+    AlreadyConnected,
+    /// The connection itself broke, there's nobody left to tell.
+    Io(io::Error)
+}
+
+impl LoginError {
+    /// What the client is told, `None` when it can't be told anything anymore.
+    pub fn reason(&self, messages: &Messages) -> Option<Chat> {
+        let message = match self {
+            LoginError::InvalidStart(_) | LoginError::InvalidName(_) | LoginError::InvalidEncryptionResponse(_) | LoginError::InvalidToken => messages.invalid_login,
+            LoginError::FailedToVerify => messages.failed_to_verify,
+            LoginError::AuthenticationUnavailable(_) => messages.authentication_unavailable,
+            LoginError::ServerFull => messages.server_full,
+            LoginError::AlreadyConnected => messages.already_connected,
+            LoginError::Io(_) => return None
         };
 
-        trace!("Sent encryption request to {} ({})", packet.name, addr);
-        stream.write_packet(encryption_request).await.unwrap();
+        Some(Chat::new(message))
+    }
+}
 
-        let encryption_response_req: io::Result<crate::packet::login::EncryptionResponse> = stream.receive().await;
-        if let Ok(encryption_response) = encryption_response_req {
-            trace!("Received encryption response from {} ({})", packet.name, addr);
+impl From<io::Error> for LoginError {
+    fn from(error: io::Error) -> Self {
+        LoginError::Io(error)
+    }
+}
 
-            let decrypted_token = encryption_response.decrypt_token(&server.get_rsa(), token_bytes.len());
-            if !std::str::from_utf8(&decrypted_token).unwrap().eq(&token) {
-                return Err(Error::new(ErrorKind::Other, "Invalid login token received."));
-            }
+impl fmt::Display for LoginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoginError::InvalidStart(error) => write!(f, "Invalid login process initiation: {}", error),
+            LoginError::InvalidName(name) => write!(f, "Invalid username \"{}\".", name),
+            LoginError::InvalidEncryptionResponse(error) => write!(f, "Invalid encryption response: {}", error),
+            LoginError::InvalidToken => write!(f, "Invalid login token received."),
+            LoginError::FailedToVerify => write!(f, "Failed to verify username."),
+            LoginError::AuthenticationUnavailable(error) => write!(f, "Couldn't reach the session server: {}", error),
+            LoginError::ServerFull => write!(f, "The server is full."),
+            LoginError::AlreadyConnected => write!(f, "Player is already connected."),
+            LoginError::Io(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+/// Logs the client in and adds them to `players`, returning who they are along with the shared
+/// secret the rest of the connection is encrypted with, which offline mode goes without.
+///
+/// Clients that don't make it are sent a Disconnect with the reason from the config's messages.
+pub async fn attempt_login<T: crate::server::Server>(config: ProxyConfig, server: &T, authenticator: &dyn Authenticator, players: &Arc<PlayerList>, stream: &mut TcpStream, addr: net::SocketAddr, handshake: &handshake::Packet) -> Result<(Player, Option<Vec<u8>>, Registration), LoginError> {
+    let mut secret = None;
 
-            let secret = encryption_response.decrypt_secret(&server.get_rsa());
+    let result = match login(&config, server, authenticator, stream, addr, handshake, &mut secret).await {
+        Ok(player) => admit(&config, players, player),
+        Err(error) => Err(error)
+    };
 
-            let resp = reqwest::get(&format!("https://sessionserver.mojang.com/session/minecraft/hasJoined?username={}&serverId={}",
-            packet.name,
-            crate::util::hash::server_hash("", &secret, &server.get_rsa().public_key_to_der().unwrap())))
-                .await.unwrap()
-                .json::<Player>()
-                .await.unwrap();
+    match result {
+        Ok((player, registration)) => Ok((player, secret, registration)),
+        Err(error) => {
+            if let Some(chat) = error.reason(&config.messages) {
+                let disconnect = login::Disconnect {
+                    chat
+                };
 
-            trace!("Authenticated {} ({})", packet.name, addr);
+                // the client turns encryption on as soon as it has sent its response
+                let sent = match &secret {
+                    Some(secret) => EncryptedStream::new(&mut *stream, secret).write_packet(disconnect, handshake.version).await,
+                    None => stream.write_packet(disconnect, handshake.version).await
+                };
+
+                if let Err(error) = sent {
+                    debug!("Couldn't tell {} why their login failed: {}", addr, error);
+                }
+            }
 
-            return Ok((resp, secret))
-        } else {
-            return Err(Error::new(ErrorKind::Other, "Invalid encryption response."));
+            Err(error)
         }
+    }
+}
+
+/// Adds a logged in `player` to `players`, as long as there's room for them and the config's
+/// `duplicate_login` policy lets them replace anyone already connected as them.
+fn admit(config: &ProxyConfig, players: &Arc<PlayerList>, player: Player) -> Result<(Player, Registration), LoginError> {
+    let capacity = if config.has_permission(&player, BYPASS_MAX_PLAYERS) {
+        None
     } else {
-        return Err(Error::new(ErrorKind::Other, "Invalid login process initiation."));
+        Some(config.max_players.max(0) as usize)
+    };
+    let replace = config.duplicate_login == DuplicateLogin::KickOld;
+
+    let (registration, replaced) = players.admit(player.clone(), capacity, replace)
+        .map_err(|refusal| match refusal {
+            Refusal::Full => LoginError::ServerFull,
+            Refusal::AlreadyConnected => LoginError::AlreadyConnected
+        })?;
+
+    for existing in replaced {
+        debug!("{} logged in again, kicking the old connection.", player.name);
+
+        // a connection that isn't relayed yet is dropped from the list all the same
+        let _ = existing.request(Request::Kick(Box::new(Chat::new(config.messages.logged_in_elsewhere))));
+    }
+
+    Ok((player, registration))
+}
+
+async fn login<T: crate::server::Server>(config: &ProxyConfig, server: &T, authenticator: &dyn Authenticator, stream: &mut TcpStream, addr: net::SocketAddr, handshake: &handshake::Packet, secret: &mut Option<Vec<u8>>) -> Result<Player, LoginError> {
+    let start = stream.receive::<login::Start>(handshake.version).await
+        .map_err(LoginError::InvalidStart)?;
+
+    debug!("User \"{}\" initiating login process.", start.name);
+
+    if !config.online_mode {
+        // nobody vouches for offline names, so they're held to what a Mojang account could be called
+        if !is_valid_name(&start.name) {
+            return Err(LoginError::InvalidName(start.name));
+        }
+
+        let mut player = Player::offline(start.name);
+        player.address = Some(addr);
+
+        trace!("Logged in {} ({}) without authentication.", player.name, addr);
+
+        return Ok(player)
+    }
+
+    let token = rand::thread_rng().gen::<[u8; 4]>().to_vec();
+    let keys = server.get_key_pair();
+    let public_key = keys.public_key();
+
+    stream.write_packet(login::EncryptionRequest {
+        id: String::from(""),
+        public_key: public_key.to_owned(),
+        token: token.to_owned()
+    }, handshake.version).await?;
+
+    trace!("Sent encryption request to {} ({})", start.name, addr);
+
+    let response = stream.receive::<login::EncryptionResponse>(handshake.version).await
+        .map_err(LoginError::InvalidEncryptionResponse)?;
+
+    trace!("Received encryption response from {} ({})", start.name, addr);
+
+    let shared = response.decrypt_secret(keys.rsa()).map_err(LoginError::InvalidEncryptionResponse)?;
+    *secret = Some(shared.to_owned());
+
+    if response.decrypt_token(keys.rsa()).map_err(LoginError::InvalidEncryptionResponse)? != token {
+        return Err(LoginError::InvalidToken);
+    }
+
+    let hash = crate::util::hash::server_hash("", &shared, public_key);
+    let mut player = authenticator.has_joined(&start.name, &hash, Some(addr.ip())).await
+        .map_err(LoginError::AuthenticationUnavailable)?
+        .ok_or(LoginError::FailedToVerify)?;
+
+    player.address = Some(addr);
+
+    trace!("Authenticated {} ({})", start.name, addr);
+
+    Ok(player)
+}
+
+/// Whether `name` could belong to a Mojang account: 1 to 16 letters, digits and underscores.
+fn is_valid_name(name: &str) -> bool {
+    (1..=16).contains(&name.len()) && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+    use std::sync::Mutex;
+    use async_trait::async_trait;
+    use openssl::rsa::{Padding, Rsa};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use crate::command::testing;
+    use crate::packet::version;
+    use crate::server::Server;
+
+    const SECRET: [u8; 16] = [7; 16];
+
+    /// Vouches for `player` whoever asks, writing down what it was asked.
+    struct FakeAuthenticator {
+        player: Option<Player>,
+        asked: Mutex<Vec<(String, String)>>
+    }
+
+    impl FakeAuthenticator {
+        fn new(player: Option<Player>) -> Self {
+            FakeAuthenticator {
+                player,
+                asked: Mutex::new(Vec::new())
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Authenticator for FakeAuthenticator {
+        async fn has_joined(&self, name: &str, server_hash: &str, _ip: Option<IpAddr>) -> io::Result<Option<Player>> {
+            self.asked.lock().unwrap().push((name.to_owned(), server_hash.to_owned()));
+            Ok(self.player.clone())
+        }
+    }
+
+    async fn read_bytes(buffer: &mut &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; buffer.read_varint().await.unwrap() as usize];
+        buffer.read_exact(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    /// Logs in as Notch the way an online mode client does, encrypting `SECRET` with whatever
+    /// key the proxy sends.
+    async fn client(mut stream: TcpStream) {
+        stream.write_packet(login::Start {
+            name: "Notch".to_owned()
+        }, version::MAXIMUM).await.unwrap();
+
+        let request = stream.read_frame().await.unwrap();
+        let mut payload = &request.payload[..];
+        payload.read_string().await.unwrap();
+        let public_key = Rsa::public_key_from_der(&read_bytes(&mut payload).await).unwrap();
+        let token = read_bytes(&mut payload).await;
+
+        let mut response = Vec::new();
+        response.write_varint(0x01).await.unwrap();
+        for field in &[&SECRET[..], &token] {
+            let mut encrypted = vec![0; public_key.size() as usize];
+            let length = public_key.public_encrypt(field, &mut encrypted, Padding::PKCS1).unwrap();
+            response.write_varint(length as i32).await.unwrap();
+            response.write_all(&encrypted[..length]).await.unwrap();
+        }
+
+        stream.write_varint(response.len() as i32).await.unwrap();
+        stream.write_all(&response).await.unwrap();
+    }
+
+    async fn log_in(server: &testing::TestServer, authenticator: &FakeAuthenticator) -> Result<(Player, Option<Vec<u8>>, Registration), LoginError> {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = tokio::spawn(client(TcpStream::connect(listener.local_addr().unwrap()).await.unwrap()));
+        let (mut stream, addr) = listener.accept().await.unwrap();
+
+        let handshake = handshake::Packet {
+            version: version::MAXIMUM,
+            address: "localhost".to_owned(),
+            port: 25570,
+            next_state: 2
+        };
+
+        let result = attempt_login(ProxyConfig::default(), server, authenticator, &Arc::new(PlayerList::new()), &mut stream, addr, &handshake).await;
+        client.await.unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn asks_the_authenticator_about_the_secret_the_client_sent() {
+        let server = testing::server(true);
+        let authenticator = FakeAuthenticator::new(Some(Player::offline("Notch")));
+
+        let (player, secret, _registration) = log_in(&server, &authenticator).await.unwrap();
+
+        let hash = crate::util::hash::server_hash("", &SECRET, server.get_key_pair().public_key());
+        assert_eq!(*authenticator.asked.lock().unwrap(), vec![("Notch".to_owned(), hash)]);
+        assert_eq!(player.name, "Notch");
+        assert!(player.address.is_some());
+        assert_eq!(secret, Some(SECRET.to_vec()));
+    }
+
+    #[tokio::test]
+    async fn refuses_players_the_authenticator_doesnt_know() {
+        let server = testing::server(true);
+        let authenticator = FakeAuthenticator::new(None);
+
+        assert!(matches!(log_in(&server, &authenticator).await, Err(LoginError::FailedToVerify)));
+        assert_eq!(authenticator.asked.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn accepts_names_a_mojang_account_could_have() {
+        for name in &["Notch", "jeb_", "a", "_", "0123456789abcdef"] {
+            assert!(is_valid_name(name), "{} should be valid", name);
+        }
+    }
+
+    #[test]
+    fn rejects_everything_else() {
+        for name in &["", "0123456789abcdefg", "Not ch", "Nötch", "Notch\0", "§cNotch", "../Notch"] {
+            assert!(!is_valid_name(name), "{:?} should be invalid", name);
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,2 +1,6 @@
 pub mod slp;
-pub mod login;
\ No newline at end of file
+pub mod login;
+pub mod relay;
+pub mod backend;
+pub mod auth;
+pub mod status;
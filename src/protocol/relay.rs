@@ -0,0 +1,632 @@
+use std::io;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, WriteHalf};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use async_trait::async_trait;
+use log::{info, debug, trace};
+use uuid::Uuid;
+use crate::config::{ProxyConfig, ServerConfig};
+use crate::packet::{AsyncPacketReadExt, AsyncPacketWriteExt, Chat};
+use crate::packet::{handshake, login};
+use crate::packet::play::{self, ClientboundChat, ClientboundKeepAlive, Dimension, JoinGame, ServerboundKeepAlive};
+use crate::packet::frame::RawPacket;
+use crate::packet::compression::CompressedStream;
+use crate::packet::registry::{self, State, Direction, Kind};
+use crate::player::{Player, Registration};
+use crate::protocol::backend::{self, ConnectError};
+
+/// Something that wants to look at play packets on their way through the relay.
+///
+/// Only packets of the kinds a handler is interested in are ever decoded, everything else is
+/// passed along as a `RawPacket` without being looked at.
+#[async_trait]
+pub trait Handler: Send + Sync {
+    fn interests(&self) -> Vec<Kind>;
+
+    /// Handles one of the packets `interests` asked for on its way in `direction`, returning the
+    /// packet to pass along or `None` to swallow it.
+    async fn handle(&self, player: &Player, direction: Direction, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>>;
+
+    /// Called once `player` has left, to forget whatever was kept about them.
+    fn disconnected(&self, _player: &Player) {}
+}
+
+/// Which handlers want to see which packet ids, resolved once per connection.
+struct Interests {
+    handlers: HashMap<(Direction, i32), Vec<Arc<dyn Handler>>>
+}
+
+impl Interests {
+    fn new(handlers: &[Arc<dyn Handler>], version: i32) -> Self {
+        let mut interests: HashMap<_, Vec<Arc<dyn Handler>>> = HashMap::new();
+
+        for handler in handlers {
+            for kind in handler.interests() {
+                let id = match registry::id_of(kind, version) {
+                    Some(id) => id,
+                    None => continue
+                };
+
+                for direction in &[Direction::Serverbound, Direction::Clientbound] {
+                    if registry::lookup(State::Play, *direction, version, id) == Some(kind) {
+                        interests.entry((*direction, id)).or_default().push(handler.clone());
+                    }
+                }
+            }
+        }
+
+        Interests {
+            handlers: interests
+        }
+    }
+
+    async fn handle(&self, player: &Player, direction: Direction, packet: RawPacket, version: i32) -> io::Result<Option<RawPacket>> {
+        let handlers = match self.handlers.get(&(direction, packet.id)) {
+            Some(handlers) => handlers,
+            None => return Ok(Some(packet))
+        };
+
+        let mut packet = packet;
+        for handler in handlers {
+            packet = match handler.handle(player, direction, packet, version).await? {
+                Some(packet) => packet,
+                None => return Ok(None)
+            };
+        }
+
+        Ok(Some(packet))
+    }
+}
+
+/// How many packets a side's reader gets ahead of the relay before it waits for the relay to
+/// catch up, and so stops reading from its socket.
+const EVENT_QUEUE_SIZE: usize = 64;
+/// How many requests can wait for a player's relay at once.
+const REQUEST_QUEUE_SIZE: usize = 32;
+
+/// Asks a player's relay to do something on their behalf, see `Player::request`.
+#[derive(Debug)]
+pub enum Request {
+    /// Move the player to the server with this id.
+    Connect(String),
+    /// Disconnect the player from the proxy with this reason.
+    Kick(Box<Chat>),
+    /// Show the player this message in chat.
+    Message(Box<Chat>),
+    /// Write this packet to the player's client, already encoded for their version.
+    Send(RawPacket)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Client,
+    /// Backends are numbered so that anything still queued up from the previous one after a
+    /// switch can be told apart and dropped.
+    Backend(u32)
+}
+
+enum Event {
+    Packet(Side, RawPacket),
+    Closed(Side, io::Result<()>),
+    Requested(Request),
+    /// A switch started while the backend with this generation was current finished.
+    Switched(u32, ServerConfig, Result<Box<(CompressedStream<TcpStream>, JoinGame)>, ConnectError>),
+    /// The backend kicked the player and a fallback server either took them in or nobody did.
+    FellBack {
+        from: String,
+        reason: Box<Chat>,
+        to: Option<Box<(ServerConfig, CompressedStream<TcpStream>, JoinGame)>>
+    }
+}
+
+/// The backend the player is currently on.
+struct Backend {
+    writer: WriteHalf<CompressedStream<TcpStream>>,
+    generation: u32,
+    // dropping this stops the reader, see `spawn_reader`
+    _stop: oneshot::Sender<()>
+}
+
+impl Backend {
+    fn attach(stream: CompressedStream<TcpStream>, generation: u32, events: &mpsc::Sender<Event>) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+
+        Backend {
+            writer,
+            generation,
+            _stop: spawn_reader(reader, Side::Backend(generation), events.clone())
+        }
+    }
+
+    /// Swaps this backend out for `stream`, closing the connection to the old one.
+    async fn replace(&mut self, stream: CompressedStream<TcpStream>, events: &mpsc::Sender<Event>) {
+        let mut old = std::mem::replace(self, Backend::attach(stream, self.generation + 1, events));
+        let _ = old.writer.shutdown().await;
+    }
+}
+
+/// Connects the player to `server`, or the first fallback server that lets them in if it doesn't,
+/// finishes the client's login with our own `Success` packet (enabling compression first if the
+/// config asks for it) and then relays play traffic until the client hangs up.
+///
+/// Players are moved between backends as they request, and to a fallback server when the one
+/// they're on kicks them. Their `registration` is kept up to date along the way and given up once
+/// they leave.
+pub async fn relay<C>(mut player: Player, registration: Registration, mut client: CompressedStream<C>, server: ServerConfig, config: &ProxyConfig, handshake: &handshake::Packet, handlers: &[Arc<dyn Handler>]) -> io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static
+{
+    let version = handshake.version;
+
+    let (server, backend, mut notice) = match connect(config, &server, handshake, &player).await {
+        Ok(backend) => (server, backend, None),
+        Err(error) => {
+            debug!("Failed to connect {} to {}: {}", player.name, server.id, error);
+            let reason = kick_reason(&server, error);
+
+            let mut fallback = None;
+            for candidate in config.get_fallback_servers(server.id) {
+                match connect(config, &candidate, handshake, &player).await {
+                    Ok(backend) => {
+                        fallback = Some((candidate, backend));
+                        break;
+                    },
+                    Err(error) => debug!("Failed to connect {} to {}: {}", player.name, candidate.id, error)
+                }
+            }
+
+            match fallback {
+                Some((candidate, backend)) => (candidate, backend, Some((server.id.to_owned(), reason))),
+                None => {
+                    // the client is still logging in, so it's told through the login Disconnect
+                    client.write_packet(login::Disconnect {
+                        chat: reason
+                    }, version).await?;
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    info!("{} connected to {}.", player.name, server.id);
+
+    if config.compression_threshold >= 0 {
+        client.write_packet(login::SetCompression {
+            threshold: config.compression_threshold
+        }, version).await?;
+
+        client.set_threshold(config.compression_threshold);
+    }
+
+    client.write_packet(login::Success {
+        uuid: player.id,
+        name: player.name.to_owned()
+    }, version).await?;
+
+    trace!("Sent login success to {}, relaying play traffic.", player.name);
+
+    let interests = Interests::new(handlers, version);
+    let disconnect = registry::id_of(Kind::PlayDisconnect, version);
+    let keep_alives = (registry::id_of(Kind::ClientboundKeepAlive, version), registry::id_of(Kind::ServerboundKeepAlive, version));
+    // the keep alive the current backend is waiting on an answer to
+    let mut keep_alive = None;
+
+    let (requests, mut requested) = mpsc::channel(REQUEST_QUEUE_SIZE);
+    player.relay = Some(requests);
+    player.server = Some(server.id.to_owned());
+    // whoever replaced us before there was a relay to kick couldn't kick us, so we go on our own
+    if !registration.update(&player) {
+        let _ = player.request(Request::Kick(Box::new(Chat::new(config.messages.logged_in_elsewhere))));
+    }
+
+    let (client_reader, mut client_writer) = tokio::io::split(client);
+
+    // both sides are read on their own tasks, which wait for room in the queue before reading
+    // on, so a side sending faster than the other takes it in is slowed down by TCP rather than
+    // buffered here. Writes in either direction happen below one at a time, so a slow client
+    // holds up what's sent to the backend as well.
+    let (events, mut receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+    let _stop_client = spawn_reader(client_reader, Side::Client, events.clone());
+    let mut backend = Backend::attach(backend, 0, &events);
+    let mut switching = false;
+    // while falling back there's no backend to talk to, the kicked one's packets are dropped
+    let mut falling_back = false;
+
+    let result = loop {
+        let event = tokio::select! {
+            Some(event) = receiver.recv() => event,
+            Some(request) = requested.recv() => Event::Requested(request),
+            else => break Ok(())
+        };
+
+        // the backend kicking the player or going away on its own sends them to a fallback
+        let kicked = match &event {
+            Event::Packet(Side::Backend(generation), packet) if *generation == backend.generation && !falling_back && Some(packet.id) == disconnect => {
+                Some(disconnect_reason(packet, version).await)
+            },
+            Event::Closed(Side::Backend(generation), result) if *generation == backend.generation && !falling_back => {
+                if let Err(error) = result {
+                    debug!("Lost connection to {} for {}: {}", player.server.as_deref().unwrap_or("nowhere"), player.name, error);
+                }
+
+                Some(Chat::text(format!("Lost connection to {}.", player.server.as_deref().unwrap_or("the server"))).color("red"))
+            },
+            _ => None
+        };
+
+        if let Some(reason) = kicked {
+            // a switch still underway is abandoned, see `Event::Switched` below
+            switching = false;
+            falling_back = true;
+
+            let from = player.server.to_owned().unwrap_or_default();
+            let servers = config.get_fallback_servers(&from);
+            let mut events = events.clone();
+            let config = config.clone();
+            let handshake = handshake.clone();
+            let player = player.clone();
+            tokio::spawn(async move {
+                let to = fall_back(servers, &config, &handshake, &player).await;
+                let _ = events.send(Event::FellBack { from, reason: Box::new(reason), to: to.map(Box::new) }).await;
+            });
+            continue;
+        }
+
+        match event {
+            Event::Packet(side, packet) => {
+                let direction = match side {
+                    Side::Client if falling_back => continue,
+                    Side::Client => Direction::Serverbound,
+                    Side::Backend(generation) if generation == backend.generation && !falling_back => Direction::Clientbound,
+                    Side::Backend(_) => continue
+                };
+
+                // an answer to the previous backend's keep alive would get the player kicked from
+                // the current one, which never sent it
+                match direction {
+                    Direction::Clientbound if Some(packet.id) == keep_alives.0 => match packet.decode::<ClientboundKeepAlive>(version).await {
+                        Ok(sent) => keep_alive = Some(sent.id),
+                        Err(error) => break Err(error)
+                    },
+                    Direction::Serverbound if Some(packet.id) == keep_alives.1 => match packet.decode::<ServerboundKeepAlive>(version).await {
+                        Ok(answer) if Some(answer.id) == keep_alive => keep_alive = None,
+                        Ok(answer) => {
+                            trace!("Dropped {}'s answer to keep alive {}, which {} didn't send.", player.name, answer.id, player.server.as_deref().unwrap_or("the server"));
+                            continue;
+                        },
+                        Err(error) => break Err(error)
+                    },
+                    _ => {}
+                }
+
+                let packet = match interests.handle(&player, direction, packet, version).await {
+                    Ok(Some(packet)) => packet,
+                    Ok(None) => continue,
+                    Err(error) => break Err(error)
+                };
+
+                let written = match direction {
+                    Direction::Serverbound => backend.writer.write_packet(packet, version).await,
+                    Direction::Clientbound => client_writer.write_packet(packet, version).await
+                };
+
+                if let Err(error) = written {
+                    break Err(error);
+                }
+
+                // the reason we didn't end up on the server we wanted waits until the client is
+                // in a world to show it in, which the first packet from the backend puts it in
+                if direction == Direction::Clientbound {
+                    if let Some((from, reason)) = notice.take() {
+                        if let Err(error) = send_kick_notice(&mut client_writer, &from, reason, version).await {
+                            break Err(error);
+                        }
+                    }
+                }
+            },
+
+            Event::Closed(Side::Client, result) => break result,
+            Event::Closed(Side::Backend(_), _) => continue,
+
+            Event::Requested(Request::Kick(reason)) => {
+                info!("{} was kicked from the proxy.", player.name);
+
+                let _ = client_writer.write_packet(play::Disconnect {
+                    chat: *reason
+                }, version).await;
+                break Ok(());
+            },
+
+            Event::Requested(Request::Message(message)) => {
+                if let Err(error) = send_message(&mut client_writer, *message, version).await {
+                    break Err(error);
+                }
+            },
+
+            Event::Requested(Request::Send(packet)) => {
+                if let Err(error) = client_writer.write_packet(packet, version).await {
+                    break Err(error);
+                }
+            },
+
+            Event::Requested(Request::Connect(id)) => {
+                let target = match config.get_server(&id) {
+                    Some(target) => target.clone(),
+                    None => {
+                        if let Err(error) = send_message(&mut client_writer, Chat::text(format!("There is no server called {}.", id)).color("red"), version).await {
+                            break Err(error);
+                        }
+                        continue;
+                    }
+                };
+
+                if switching || falling_back || player.server.as_deref() == Some(target.id) {
+                    let message = if switching || falling_back {
+                        String::from("You're already being moved to another server.")
+                    } else {
+                        format!("You're already connected to {}.", target.id)
+                    };
+
+                    if let Err(error) = send_message(&mut client_writer, Chat::text(message).color("red"), version).await {
+                        break Err(error);
+                    }
+                    continue;
+                }
+
+                switching = true;
+
+                let generation = backend.generation;
+                let mut events = events.clone();
+                let config = config.clone();
+                let handshake = handshake.clone();
+                let player = player.clone();
+                tokio::spawn(async move {
+                    let result = join(&target, &config, &handshake, &player).await;
+                    let _ = events.send(Event::Switched(generation, target, result.map(Box::new))).await;
+                });
+            },
+
+            // the player got kicked while switching and is being or was already moved elsewhere,
+            // where they stay
+            Event::Switched(generation, target, _) if falling_back || generation != backend.generation => {
+                debug!("Dropped {}'s switch to {}, they were moved to a fallback server meanwhile.", player.name, target.id);
+            },
+
+            Event::Switched(_, target, Ok(joined)) => {
+                switching = false;
+
+                let (stream, join_game) = *joined;
+
+                backend.replace(stream, &events).await;
+                keep_alive = None;
+
+                if let Err(error) = reset_world(&mut client_writer, join_game, version).await {
+                    break Err(error);
+                }
+
+                info!("{} switched from {} to {}.", player.name, player.server.as_deref().unwrap_or("nowhere"), target.id);
+                player.server = Some(target.id.to_owned());
+                registration.update(&player);
+            },
+
+            Event::Switched(_, target, Err(error)) => {
+                switching = false;
+
+                debug!("Failed to switch {} to {}: {}", player.name, target.id, error);
+
+                if let Err(error) = send_kick_notice(&mut client_writer, target.id, kick_reason(&target, error), version).await {
+                    break Err(error);
+                }
+            },
+
+            Event::FellBack { from, reason, to: Some(fallback) } => {
+                falling_back = false;
+
+                let (target, stream, join_game) = *fallback;
+
+                backend.replace(stream, &events).await;
+                keep_alive = None;
+
+                let reset = match reset_world(&mut client_writer, join_game, version).await {
+                    Ok(()) => send_kick_notice(&mut client_writer, &from, *reason, version).await,
+                    Err(error) => Err(error)
+                };
+
+                if let Err(error) = reset {
+                    break Err(error);
+                }
+
+                info!("{} was kicked from {} and moved to {}.", player.name, from, target.id);
+                player.server = Some(target.id.to_owned());
+                registration.update(&player);
+            },
+
+            Event::FellBack { from, reason, to: None } => {
+                info!("{} was kicked from {} with nowhere to fall back to.", player.name, from);
+
+                let _ = client_writer.write_packet(play::Disconnect {
+                    chat: *reason
+                }, version).await;
+                break Ok(());
+            }
+        }
+    };
+
+    // whichever side is still open gets closed along with the other one
+    let _ = client_writer.shutdown().await;
+    let _ = backend.writer.shutdown().await;
+
+    for handler in handlers {
+        handler.disconnected(&player);
+    }
+
+    info!("{} disconnected.", player.name);
+
+    result
+}
+
+/// Tries `servers` in order, returning the first one that lets `player` in.
+async fn fall_back(servers: Vec<ServerConfig>, config: &ProxyConfig, handshake: &handshake::Packet, player: &Player) -> Option<(ServerConfig, CompressedStream<TcpStream>, JoinGame)> {
+    for server in servers {
+        match join(&server, config, handshake, player).await {
+            Ok((stream, join_game)) => return Some((server, stream, join_game)),
+            Err(error) => debug!("Failed to connect {} to {}: {}", player.name, server.id, error)
+        }
+    }
+
+    None
+}
+
+/// What a backend's Disconnect says. A reason that isn't a component we can read is shown as the
+/// JSON it came as, the player is sent to a fallback either way.
+async fn disconnect_reason(packet: &RawPacket, version: i32) -> Chat {
+    match packet.decode::<play::Disconnect>(version).await {
+        Ok(disconnect) => disconnect.chat,
+        Err(error) => {
+            debug!("Couldn't read a kick reason, passing it on as it is: {}", error);
+
+            let mut payload = packet.payload.as_ref();
+            Chat::text(payload.read_string().await.unwrap_or_default())
+        }
+    }
+}
+
+fn kick_reason(server: &ServerConfig, error: ConnectError) -> Chat {
+    match error {
+        ConnectError::Kicked(reason) => *reason,
+        ConnectError::Io(_) => Chat::text(format!("Failed to connect to {}!", server.id)).color("red")
+    }
+}
+
+async fn send_kick_notice<W>(client: &mut W, from: &str, reason: Chat, version: i32) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + Send + Sync
+{
+    send_message(client, Chat::text(format!("You were kicked from {}:", from)).color("red"), version).await?;
+    send_message(client, reason, version).await
+}
+
+/// Logs `player` into `server` and waits for the Join Game that describes the world they'll be
+/// put into there. Gives up after the config's `connect_timeout`.
+async fn join(server: &ServerConfig, config: &ProxyConfig, handshake: &handshake::Packet, player: &Player) -> Result<(CompressedStream<TcpStream>, JoinGame), ConnectError> {
+    let version = handshake.version;
+
+    let joining = async {
+        let mut backend = backend::connect(config, server, handshake, player).await?;
+
+        loop {
+            let packet = backend.read_frame().await?;
+
+            // Join Game is the first thing a backend sends in play, anything before it is dropped
+            if registry::lookup(State::Play, Direction::Clientbound, version, packet.id) == Some(Kind::JoinGame) {
+                let join_game = packet.decode::<JoinGame>(version).await?;
+                return Ok((backend, join_game));
+            }
+        }
+    };
+
+    time::timeout(Duration::from_secs(config.connect_timeout), joining).await
+        .unwrap_or_else(|_| Err(timed_out(server)))
+}
+
+/// `backend::connect`, giving up after the config's `connect_timeout`.
+async fn connect(config: &ProxyConfig, server: &ServerConfig, handshake: &handshake::Packet, player: &Player) -> Result<CompressedStream<TcpStream>, ConnectError> {
+    time::timeout(Duration::from_secs(config.connect_timeout), backend::connect(config, server, handshake, player)).await
+        .unwrap_or_else(|_| Err(timed_out(server)))
+}
+
+fn timed_out(server: &ServerConfig) -> ConnectError {
+    ConnectError::Io(io::Error::new(io::ErrorKind::TimedOut, format!("{} took too long to let the player in.", server.id)))
+}
+
+/// Moves the client into the world described by a new backend's `join_game`. Clients happily take
+/// another Join Game, which spares us from rewriting entity ids, but it has to be for a different
+/// dimension than the Respawn that follows it or the client keeps parts of the old world around.
+async fn reset_world<W>(client: &mut W, join_game: JoinGame, version: i32) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + Send + Sync
+{
+    let respawn = join_game.respawn();
+
+    let mut temporary = join_game;
+    temporary.dimension = respawn.dimension.other();
+    if let Dimension::Named(name) = &temporary.dimension {
+        temporary.world_name = name.to_owned();
+    }
+
+    client.write_packet(temporary, version).await?;
+    client.write_packet(respawn, version).await
+}
+
+async fn send_message<W>(client: &mut W, chat: Chat, version: i32) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + Send + Sync
+{
+    client.write_packet(ClientboundChat {
+        chat,
+        position: 1,
+        sender: Uuid::nil()
+    }, version).await
+}
+
+fn spawn_reader<R>(reader: R, side: Side, events: mpsc::Sender<Event>) -> oneshot::Sender<()>
+where
+    R: AsyncRead + Unpin + Send + Sync + 'static
+{
+    let (stop, stopped) = oneshot::channel();
+    // frames are read a VarInt byte at a time, which shouldn't mean a syscall each
+    tokio::spawn(read_packets(BufReader::new(reader), side, events, stopped));
+
+    stop
+}
+
+async fn read_packets<R>(mut reader: R, side: Side, mut events: mpsc::Sender<Event>, mut stop: oneshot::Receiver<()>)
+where
+    R: AsyncRead + Unpin + Send + Sync
+{
+    loop {
+        let result = tokio::select! {
+            result = reader.read_frame() => result,
+            _ = &mut stop => return
+        };
+
+        let event = match result {
+            Ok(packet) => Event::Packet(side, packet),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Event::Closed(side, Ok(())),
+            Err(error) => Event::Closed(side, Err(error))
+        };
+
+        let closed = matches!(event, Event::Closed(..));
+        if events.send(event).await.is_err() || closed {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::version::V1_12_2;
+
+    async fn disconnect(json: &str) -> RawPacket {
+        let mut payload = Vec::new();
+        payload.write_string(json.to_owned()).await.unwrap();
+        RawPacket::new(registry::id_of(Kind::PlayDisconnect, V1_12_2).unwrap(), payload.into())
+    }
+
+    #[tokio::test]
+    async fn reads_kick_reasons() {
+        let packet = disconnect(r#"{"text":"Server closed"}"#).await;
+        assert_eq!(disconnect_reason(&packet, V1_12_2).await, Chat::text("Server closed"));
+    }
+
+    #[tokio::test]
+    async fn shows_unreadable_kick_reasons_as_they_are() {
+        let packet = disconnect(r#"{"text":"Server closed","with":3}"#).await;
+        assert_eq!(disconnect_reason(&packet, V1_12_2).await, Chat::text(r#"{"text":"Server closed","with":3}"#));
+    }
+}
@@ -1,54 +1,168 @@
 use std::{io, net};
 use tokio::net::{TcpStream};
-use log::{info, error};
-use crate::packet::{In, AsyncPacketReadExt, AsyncPacketWriteExt};
-use crate::packet::handshake;
+use log::{info, debug, error};
+use crate::packet::{AsyncPacketReadExt, AsyncPacketWriteExt};
+use crate::packet::{handshake, Chat};
+use crate::packet::registry;
+use crate::packet::version;
 use std::io::{Error, ErrorKind};
-use crate::util::color::Color;
+use crate::config::StatusMode;
+use crate::protocol::status::{self, StatusCache, Counts};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-pub async fn attempt_server_list_ping<T: crate::server::Server>(config: crate::config::ProxyConfig, server: &T, stream: &mut TcpStream, addr: net::SocketAddr) -> io::Result<handshake::Packet> {
-    if let Ok(handshake) = crate::packet::handshake::Packet::read(stream).await {
+/// How many players the vanilla server lists when hovering over the player count.
+const SAMPLE_SIZE: usize = 12;
+
+/// The first byte of the ping clients before 1.7 send, which predates packet framing.
+const LEGACY_PING: u8 = 0xFE;
+/// Legacy pings are answered with a kick whose reason holds the status.
+const LEGACY_KICK: u8 = 0xFF;
+/// A protocol version no legacy client speaks, which makes them show our version name.
+const LEGACY_PROTOCOL: i32 = 127;
+
+/// Answers the `0xFE 0x01` ping clients before 1.7 (and plenty of server list crawlers) send,
+/// returning whether that's what the client sent. Nothing is read from `stream` if it isn't.
+pub async fn attempt_legacy_ping<T: crate::server::Server>(config: &crate::config::ProxyConfig, server: &T, status: &StatusCache, stream: &mut TcpStream, addr: net::SocketAddr) -> io::Result<bool> {
+    let mut first = [0; 1];
+    if stream.peek(&mut first).await? == 0 || first[0] != LEGACY_PING {
+        return Ok(false);
+    }
+
+    // 1.6 clients follow up with a plugin message saying where they connected, none of which
+    // changes the answer
+    let mut request = [0; 512];
+    let _ = stream.read(&mut request).await?;
+
+    info!("Client ({}) sent a legacy server list ping.", addr);
+
+    let mut motd = Chat::new(config.motd);
+    let mut counts = Counts {
+        online: server.get_players().len() as i32,
+        max: config.max_players
+    };
+
+    let handshake = handshake::Packet {
+        version: version::MAXIMUM,
+        address: stream.local_addr()?.ip().to_string(),
+        port: stream.local_addr()?.port(),
+        next_state: 1
+    };
+
+    match config.status.mode {
+        StatusMode::Passthrough => match passthrough_server(config) {
+            Ok(backend) => match status.passthrough(&backend, &handshake).await {
+                Ok(json) => {
+                    // whatever the backend leaves out is answered ourselves
+                    if let Ok(description) = status::description(&json) {
+                        motd = description;
+                    }
+                    if let Ok(backend_counts) = status::counts(&json) {
+                        counts = backend_counts;
+                    }
+                },
+                Err(error) => debug!("Couldn't get the status of {}, answering ourselves: {}", backend.id, error)
+            },
+            Err(error) => error!("{}", error)
+        },
+        StatusMode::Aggregate => if let Some(aggregate) = status.aggregate(&config.servers, &handshake).await {
+            counts = aggregate;
+        },
+        StatusMode::Local => {}
+    }
+
+    let reason = format!("§1\0{}\0Rift {}\0{}\0{}\0{}", LEGACY_PROTOCOL, version::SUPPORTED_RANGE, motd.to_legacy(), counts.online, counts.max);
+    let reason: Vec<u16> = reason.encode_utf16().collect();
+
+    let mut kick = vec![LEGACY_KICK];
+    kick.extend_from_slice(&(reason.len() as u16).to_be_bytes());
+    for unit in reason {
+        kick.extend_from_slice(&unit.to_be_bytes());
+    }
+
+    stream.write_all(&kick).await?;
+
+    Ok(true)
+}
+
+pub async fn attempt_server_list_ping<T: crate::server::Server>(config: crate::config::ProxyConfig, server: &T, status: &StatusCache, stream: &mut TcpStream, addr: net::SocketAddr) -> io::Result<handshake::Packet> {
+    if let Ok(handshake) = stream.receive::<crate::packet::handshake::Packet>(registry::ANY_VERSION).await {
         if handshake.next_state == 2 {
             return Ok(handshake)
         }
 
-        let req: io::Result<crate::packet::handshake::Request> = stream.receive().await;
+        let req: io::Result<crate::packet::handshake::Request> = stream.receive(handshake.version).await;
         if req.is_ok() {
             info!("Client ({}) initiated handshake to proxy via {}.", addr, handshake.address);
+
+            if config.status.mode == StatusMode::Passthrough {
+                match passthrough_server(&config) {
+                    Ok(backend) => match status.passthrough(&backend, &handshake).await {
+                        Ok(json) => {
+                            stream.write_packet(handshake::RawResponse {
+                                json
+                            }, handshake.version).await?;
+
+                            return answer_ping(stream, handshake).await;
+                        },
+                        Err(error) => debug!("Couldn't get the status of {}, answering ourselves: {}", backend.id, error)
+                    },
+                    Err(error) => error!("{}", error)
+                }
+            }
+
+            let players = server.get_players();
             let mut response = handshake::Response {
                 players: handshake::Players {
                     max: config.max_players,
-                    online: server.get_players().len() as i32,
-                    sample: server.get_players()
-                },
-                description: handshake::Description {
-                    text: config.motd.to_owned().colored()
+                    online: players.len() as i32,
+                    sample: players.into_iter().take(SAMPLE_SIZE).map(handshake::SamplePlayer::from).collect()
                 },
+                description: Chat::new(config.motd),
                 version: handshake::Version {
-                    name: String::from("Rift"),
-                    protocol: handshake.version
+                    name: format!("Rift {}", version::SUPPORTED_RANGE),
+                    // anything else makes the client show the version name as incompatible
+                    protocol: if version::is_supported(handshake.version) { handshake.version } else { version::MAXIMUM }
                 },
                 favicon: None
             };
 
-            if config.favicon.is_some() {
-                response.favicon = Some(config.favicon.unwrap().to_owned());
+            if let Some(favicon) = config.favicon {
+                response.favicon = Some(favicon.to_owned());
             }
 
-            stream.write_packet(response).await.unwrap();
-
-            if let Ok(ping) = crate::packet::handshake::Ping::read(stream).await {
-                stream.write_packet(ping).await.unwrap();
-                Ok(handshake)
-            } else {
-                Err(Error::new(ErrorKind::Other, "Bad ping packet."))
+            if config.status.mode == StatusMode::Aggregate {
+                if let Some(counts) = status.aggregate(&config.servers, &handshake).await {
+                    response.players.online = counts.online;
+                    response.players.max = counts.max;
+                }
             }
+
+            stream.write_packet(response, handshake.version).await?;
+
+            answer_ping(stream, handshake).await
         } else {
-            Err(Error::new(ErrorKind::Other, "Bad request packet."))
+            Err(Error::other("Bad request packet."))
         }
     } else {
         error!("Malformed handshake packet from {}!", addr);
-        return Err(Error::new(ErrorKind::Other, "Invalid handshake packet."));
+        Err(Error::other("Invalid handshake packet."))
     }
 
-}
\ No newline at end of file
+}
+
+/// The server whose status is passed along, `status.server` or else one of the defaults.
+fn passthrough_server(config: &crate::config::ProxyConfig) -> io::Result<crate::config::ServerConfig> {
+    match config.status.server {
+        Some(id) => config.get_server(id).cloned().ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Status server {} isn't defined!", id))),
+        None => config.get_default_server()
+    }
+}
+
+async fn answer_ping(stream: &mut TcpStream, handshake: handshake::Packet) -> io::Result<handshake::Packet> {
+    if let Ok(ping) = stream.receive::<crate::packet::handshake::Ping>(handshake.version).await {
+        stream.write_packet(ping, handshake.version).await?;
+        Ok(handshake)
+    } else {
+        Err(Error::other("Bad ping packet."))
+    }
+}
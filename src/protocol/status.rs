@@ -0,0 +1,228 @@
+use std::io;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::{self, Instant};
+use log::debug;
+use serde_json::Value;
+use crate::config::{ProxyConfig, ServerConfig};
+use crate::packet::{AsyncPacketReadExt, AsyncPacketWriteExt};
+use crate::packet::{handshake, Chat};
+
+/// A server's id and the protocol version it was asked with.
+type StatusKey = (&'static str, i32);
+
+/// What backends said when last asked, so a busy server list doesn't turn into a ping for every
+/// backend per client.
+pub struct StatusCache {
+    ttl: Duration,
+    timeout: Duration,
+    /// There's more than one server to pick from without `status.server`, so answers are kept per server.
+    passthrough: Mutex<HashMap<StatusKey, (Instant, String)>>,
+    /// Held for as long as a refresh takes, so clients asking meanwhile wait for it instead of
+    /// pinging every backend themselves. Refreshes nobody answered are kept as well.
+    aggregate: AsyncMutex<Option<(Instant, Option<Counts>)>>
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counts {
+    pub online: i32,
+    pub max: i32
+}
+
+impl StatusCache {
+    pub fn new(config: &ProxyConfig) -> Self {
+        StatusCache {
+            ttl: Duration::from_secs(config.status.cache_ttl),
+            timeout: Duration::from_secs(config.status.timeout),
+            passthrough: Mutex::new(HashMap::new()),
+            aggregate: AsyncMutex::new(None)
+        }
+    }
+
+    /// The status response `server` gives clients on the same protocol version as `handshake`.
+    pub async fn passthrough(&self, server: &ServerConfig, handshake: &handshake::Packet) -> io::Result<String> {
+        let key = (server.id, handshake.version);
+        if let Some((time, json)) = self.passthrough.lock().unwrap().get(&key) {
+            if time.elapsed() < self.ttl {
+                return Ok(json.to_owned());
+            }
+        }
+
+        let json = ping(server, handshake, self.timeout).await?;
+        self.passthrough.lock().unwrap().insert(key, (Instant::now(), json.to_owned()));
+
+        Ok(json)
+    }
+
+    /// The online and max counts of every backend that answers, added up. `None` if none did.
+    pub async fn aggregate(&self, servers: &[ServerConfig], handshake: &handshake::Packet) -> Option<Counts> {
+        let mut cached = self.aggregate.lock().await;
+        if let Some((time, counts)) = *cached {
+            if time.elapsed() < self.ttl {
+                return counts;
+            }
+        }
+
+        // all of them are asked at once, the slowest only holds things up for `timeout`
+        let pings: Vec<_> = servers.iter()
+            .map(|server| {
+                let server = server.clone();
+                let handshake = handshake.clone();
+                let timeout = self.timeout;
+                tokio::spawn(async move {
+                    let result = ping(&server, &handshake, timeout).await.and_then(|json| counts(&json));
+                    (server, result)
+                })
+            })
+            .collect();
+
+        let mut total: Option<Counts> = None;
+        for ping in pings {
+            match ping.await {
+                Ok((_, Ok(counts))) => {
+                    let total = total.get_or_insert_with(Counts::default);
+                    total.online += counts.online;
+                    total.max += counts.max;
+                },
+                Ok((server, Err(error))) => debug!("Couldn't get the status of {}: {}", server.id, error),
+                Err(error) => debug!("Status ping panicked: {}", error)
+            }
+        }
+
+        *cached = Some((Instant::now(), total));
+
+        total
+    }
+}
+
+/// Asks `server` for its status the way the client in `handshake` would.
+async fn ping(server: &ServerConfig, handshake: &handshake::Packet, timeout: Duration) -> io::Result<String> {
+    let request = async {
+        let mut stream = TcpStream::connect(server.address).await?;
+
+        let mut handshake = handshake.clone();
+        handshake.next_state = 1;
+
+        let version = handshake.version;
+        stream.write_packet(handshake, version).await?;
+        stream.write_packet(handshake::Request, version).await?;
+
+        Ok(stream.receive::<handshake::RawResponse>(version).await?.json)
+    };
+
+    time::timeout(timeout, request).await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("{} took too long to answer.", server.id)))?
+}
+
+/// The online and max player counts in a status response.
+pub fn counts(json: &str) -> io::Result<Counts> {
+    let status: Value = serde_json::from_str(json)?;
+    let players = &status["players"];
+
+    match (players["online"].as_i64(), players["max"].as_i64()) {
+        (Some(online), Some(max)) => Ok(Counts {
+            online: online as i32,
+            max: max as i32
+        }),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Status response without player counts."))
+    }
+}
+
+/// The description in a status response, which is what the MOTD is called there.
+pub fn description(json: &str) -> io::Result<Chat> {
+    let status: Value = serde_json::from_str(json)?;
+
+    Ok(serde_json::from_value(status["description"].clone())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// A backend answering status requests with `online` players, counting how often it's asked.
+    async fn backend(id: &'static str, online: i32) -> (ServerConfig, Arc<AtomicUsize>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = Box::leak(listener.local_addr().unwrap().to_string().into_boxed_str());
+        let asked = Arc::new(AtomicUsize::new(0));
+
+        let counter = asked.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                tokio::spawn(async move {
+                    let handshake = stream.receive::<handshake::Packet>(crate::packet::version::V1_12_2).await.unwrap();
+                    stream.receive::<handshake::Request>(handshake.version).await.unwrap();
+
+                    // slow enough for everyone to ask before the first answer is in
+                    time::delay_for(Duration::from_millis(100)).await;
+                    stream.write_packet(handshake::RawResponse {
+                        json: format!("{{\"players\":{{\"online\":{},\"max\":20}}}}", online)
+                    }, handshake.version).await.unwrap();
+                });
+            }
+        });
+
+        (ServerConfig {
+            id,
+            address,
+            default: true
+        }, asked)
+    }
+
+    fn handshake() -> handshake::Packet {
+        handshake::Packet {
+            version: crate::packet::version::V1_12_2,
+            address: "localhost".to_owned(),
+            port: 25565,
+            next_state: 1
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregates_are_refreshed_once_for_everyone_asking() {
+        let (lobby, lobby_asked) = backend("lobby", 3).await;
+        let (hub, hub_asked) = backend("hub", 4).await;
+        let servers = vec![lobby, hub];
+        let cache = StatusCache::new(&ProxyConfig::default());
+
+        let handshake = handshake();
+        let asking = tokio::join!(
+            cache.aggregate(&servers, &handshake),
+            cache.aggregate(&servers, &handshake),
+            cache.aggregate(&servers, &handshake)
+        );
+        for counts in &[asking.0, asking.1, asking.2] {
+            let counts = counts.unwrap();
+            assert_eq!((counts.online, counts.max), (7, 40));
+        }
+
+        assert_eq!(lobby_asked.load(Ordering::SeqCst), 1);
+        assert_eq!(hub_asked.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn passthrough_is_cached_per_server() {
+        let (lobby, _) = backend("lobby", 3).await;
+        let (hub, _) = backend("hub", 4).await;
+        let cache = StatusCache::new(&ProxyConfig::default());
+
+        let handshake = handshake();
+        assert_eq!(counts(&cache.passthrough(&lobby, &handshake).await.unwrap()).unwrap().online, 3);
+        assert_eq!(counts(&cache.passthrough(&hub, &handshake).await.unwrap()).unwrap().online, 4);
+    }
+
+    #[test]
+    fn reads_the_description_whether_a_string_or_a_component() {
+        assert_eq!(description(r#"{"description":"A Minecraft Server"}"#).unwrap(), Chat::text("A Minecraft Server"));
+        assert_eq!(description(r#"{"description":{"text":"A Minecraft Server"}}"#).unwrap(), Chat::text("A Minecraft Server"));
+        assert!(description(r#"{"players":{"online":3,"max":20}}"#).is_err());
+    }
+}
@@ -1,34 +1,60 @@
 use std::{io, net};
 use std::fmt::Display;
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::net::{TcpListener, ToSocketAddrs};
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::io::BufRead;
-use tokio::time::{Instant};
-use log::{info, debug, error, trace};
-use crate::command::{CommandSender, ProxyCommandExecutor};
-use crate::player::Player;
+use tokio::io::AsyncBufReadExt;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+use log::{info, debug, error};
+use crate::command::{CommandSender, CommandExecutor, ProxyCommandExecutor};
+use crate::command::handler::CommandHandler;
+use crate::player::{Player, PlayerList};
 use crate::engine::{ProxyEngine, IntoProxyEngine};
 use crate::config::{ProxyConfig, ServerConfig};
 use std::marker::PhantomData;
-use crate::packet::{In, Out, AsyncPacketReadExt, AsyncPacketWriteExt};
-use openssl::rsa::{Rsa, Padding};
-use rand::Rng;
+use crate::packet::AsyncPacketWriteExt;
 use crate::packet::Chat;
-use std::error::Error;
+use crate::packet::encryption::EncryptedStream;
+use crate::packet::compression::CompressedStream;
+use crate::protocol::relay::{self, Handler, Request};
+use crate::protocol::auth::{Authenticator, MojangAuthenticator};
+use crate::protocol::status::StatusCache;
+use crate::util::key::{KeyPair, Keys};
+use std::sync::Arc;
+use std::path::Path;
 
 pub trait Server {
     fn get_players(&self) -> Vec<Player>;
+    /// Finds an online player by name, ignoring case.
+    fn get_player(&self, name: &str) -> Option<Player>;
     fn get_addresses(&self) -> Vec<net::SocketAddr>;
-    fn get_rsa(&self) -> Rsa<openssl::pkey::Private>;
+    fn get_key_pair(&self) -> Arc<KeyPair>;
+    /// Replaces the proxy's key pair with a freshly generated one, saving it to the configured
+    /// `key_file`.
+    fn rotate_key(&self) -> io::Result<()>;
+    /// Picks up the key pair in the configured `key_file`, e.g. after another proxy rotated it.
+    fn reload_key(&self) -> io::Result<()>;
+    /// Moves `player` over to the server with `server_id`. This only starts the switch, the player
+    /// is told if it doesn't work out.
+    fn switch_server(&self, player: &Player, server_id: &str) -> io::Result<()>;
+    fn get_servers(&self) -> Vec<ServerConfig>;
+    /// Whether `sender` was granted `permission`. The console has every permission.
+    fn has_permission(&self, sender: &dyn CommandSender, permission: &str) -> bool;
+    /// Kicks everyone and stops the proxy once they're gone, or `SHUTDOWN_GRACE` has passed.
+    fn shutdown(&self);
 }
 
+/// How long players get to be told the proxy is going away before it does.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
 #[derive(Clone)]
 struct DynServer {
-    players: Vec<Player>,
+    players: Arc<PlayerList>,
     addresses: Vec<net::SocketAddr>,
-    rsa: Rsa<openssl::pkey::Private>
+    keys: Arc<Keys>,
+    config: Arc<ProxyConfig>
 }
 
 pub struct ProxyServer<F, I, E>
@@ -37,9 +63,10 @@ where
     I: IntoProxyEngine<E>,
     E: ProxyEngine<Config = ProxyConfig, Executor = ProxyCommandExecutor>
 {
-    rsa: Rsa<openssl::pkey::Private>,
+    keys: Arc<Keys>,
     addresses: Vec<net::SocketAddr>,
-    players: Vec<Player>,
+    players: Arc<PlayerList>,
+    config: Arc<ProxyConfig>,
     engine: F,
     pub created_time: Instant,
     _i: PhantomData<E>
@@ -52,12 +79,16 @@ where
     E: ProxyEngine<Config = ProxyConfig, Executor = ProxyCommandExecutor>
 {
     pub fn new(engine: F) -> Self {
+        let config = crate::engine::into_engine(engine()).get_config().clone();
+        let keys = Keys::open(config.key_file.map(Path::new)).expect("Couldn't load the RSA key pair");
+
         ProxyServer {
-            rsa: Rsa::generate(1028).unwrap(),
+            keys: Arc::new(keys),
             addresses: Vec::new(),
-            players: Vec::<Player>::new(),
+            players: Arc::new(PlayerList::new()),
+            config: Arc::new(config),
             created_time: Instant::now(),
-            engine: engine,
+            engine,
             _i: PhantomData
         }
     }
@@ -65,8 +96,9 @@ where
     fn to_dyn(&self) -> DynServer {
         DynServer {
             addresses: self.addresses.to_vec(),
-            players: self.players.to_vec(),
-            rsa: self.rsa.clone()
+            players: self.players.clone(),
+            keys: self.keys.clone(),
+            config: self.config.clone()
         }
     }
 
@@ -85,14 +117,11 @@ where
             }
         }
 
-        return if !successful {
+        if !successful {
             if let Some(e) = error.take() {
                 Err(e)
             } else {
-                Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Failed to bind to address!",
-                ))
+                Err(io::Error::other("Failed to bind to address!"))
             }
         } else {
             Ok(sockets)
@@ -132,15 +161,60 @@ where
 
 impl Server for DynServer {
     fn get_players(&self) -> Vec<Player> {
-        self.players.to_vec()
+        self.players.all()
+    }
+
+    fn get_player(&self, name: &str) -> Option<Player> {
+        self.players.get_by_name(name)
     }
 
     fn get_addresses(&self) -> Vec<net::SocketAddr> {
         self.addresses.to_vec()
     }
 
-    fn get_rsa(&self) -> Rsa<openssl::pkey::Private> {
-        self.rsa.clone()
+    fn get_key_pair(&self) -> Arc<KeyPair> {
+        self.keys.get()
+    }
+
+    fn rotate_key(&self) -> io::Result<()> {
+        self.keys.rotate()
+    }
+
+    fn reload_key(&self) -> io::Result<()> {
+        self.keys.reload()
+    }
+
+    fn switch_server(&self, player: &Player, server_id: &str) -> io::Result<()> {
+        player.request(Request::Connect(server_id.to_owned()))
+    }
+
+    fn get_servers(&self) -> Vec<ServerConfig> {
+        self.config.servers.to_vec()
+    }
+
+    fn has_permission(&self, sender: &dyn CommandSender, permission: &str) -> bool {
+        match sender.as_player() {
+            Some(player) => self.config.has_permission(player, permission),
+            None => sender.is_console()
+        }
+    }
+
+    fn shutdown(&self) {
+        info!("Stopping the proxy.");
+
+        for player in self.players.all() {
+            let _ = player.request(Request::Kick(Box::new(Chat::new(self.config.messages.proxy_closed))));
+        }
+
+        let players = self.players.clone();
+        tokio::spawn(async move {
+            let deadline = Instant::now() + SHUTDOWN_GRACE;
+            while !players.is_empty() && Instant::now() < deadline {
+                time::delay_for(Duration::from_millis(100)).await;
+            }
+
+            std::process::exit(0);
+        });
     }
 }
 
@@ -162,7 +236,7 @@ where
 {
     type Output = io::Result<()>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
 
         let engine = &(this.server.engine);
@@ -174,10 +248,19 @@ where
         for command in &mut commands {
             command.set_backend(Box::new(this.server.to_dyn())).unwrap();
         }
+
+        let executor = Arc::new(ProxyCommandExecutor::new(commands, Box::new(this.server.to_dyn())));
+        let handlers: Vec<Arc<dyn Handler>> = vec![Arc::new(CommandHandler::new(executor.clone()))];
         
+        let authenticator: Arc<dyn Authenticator> = Arc::new(MojangAuthenticator::new(&config)?);
+        let status = Arc::new(StatusCache::new(&config));
+
         for socket in sockets {
             let server = this.server.to_dyn();
             let config = config.clone();
+            let authenticator = authenticator.clone();
+            let status = status.clone();
+            let handlers = handlers.clone();
             tokio::spawn(async move {
                 let mut listener = TcpListener::bind(socket).await.unwrap();
                 let cloned = server.clone();
@@ -185,69 +268,64 @@ where
                 loop {
                     let cloned = cloned.clone();
                     let config = config.clone();
+                    let authenticator = authenticator.clone();
+                    let status = status.clone();
+                    let handlers = handlers.clone();
                     if let Ok(client) = listener.accept().await {
-                        client.0.set_nodelay(true).unwrap();
                         let (mut stream, addr) = client;
+                        if let Err(error) = stream.set_nodelay(true) {
+                            debug!("Failed to set up the connection from {}: {}", addr, error);
+                            continue;
+                        }
+
                         tokio::spawn(async move {
                             let config = config.clone();
 
-                            match crate::protocol::slp::attempt_server_list_ping(config.clone(), &cloned, &mut stream, addr).await {
+                            match crate::protocol::slp::attempt_legacy_ping(&config, &cloned, &status, &mut stream, addr).await {
+                                Ok(false) => {},
+                                Ok(true) => return,
+                                Err(error) => {
+                                    debug!("Failed to answer legacy ping from {}: {}", addr, error);
+                                    return
+                                }
+                            }
+
+                            match crate::protocol::slp::attempt_server_list_ping(config.clone(), &cloned, &status, &mut stream, addr).await {
                                 Ok(handshake) => {
+                                    let version = handshake.version;
                                     if handshake.next_state == 2 {
+                                        if !crate::packet::version::is_supported(version) {
+                                            // like vanilla, whichever side is behind is the outdated one
+                                            let message = if version < crate::packet::version::MINIMUM {
+                                                config.messages.outdated_client
+                                            } else {
+                                                config.messages.outdated_server
+                                            };
+
+                                            let result = stream.write_packet(crate::packet::login::Disconnect {
+                                                chat: Chat::new(message.replace("{version}", crate::packet::version::SUPPORTED_RANGE))
+                                            }, version).await;
+
+                                            if let Err(error) = result {
+                                                debug!("Failed to tell {} their version isn't supported: {}", addr, error);
+                                            }
+
+                                            debug!("Rejected {} using unsupported protocol version {}.", addr, version);
+                                            return
+                                        }
+
                                         if let Ok(default_server) = config.get_default_server() {
-                                            match crate::protocol::login::attempt_login(config.clone(), &cloned, &mut stream, addr).await {
-                                                Ok((player, secret)) => {
-                                                    
-                                                   let target = default_server.get_address();
-                                                   if let Ok(mut server) = TcpStream::connect(target).await {
-                                                       trace!("Established proxy connection to {} ({}) for {}.", default_server.id, target, player.name);
-                                                       server.set_nodelay(true);
-                                                       server.write_packet(handshake.clone()).await.unwrap();
-                                                       server.write_packet(crate::packet::login::Start {
-                                                           name: player.name.to_owned()
-                                                       }).await.unwrap();
-
-                                                       let success_packet: io::Result<crate::packet::login::Success> = server.receive().await;
-                                                       if success_packet.is_ok() {
-                                                           let success_packet = crate::packet::login::Success {
-                                                               uuid: player.id.to_string(),
-                                                               name: player.name.to_owned()
-                                                           };
-
-                                                           info!("Got login success packet!");
-                                                       } else {
-                                                           let error = format!("{}", success_packet.err().unwrap());
-                                                           if let Ok(packet_id) = error.parse::<i32>() {
-                                                                if packet_id == 0 { // disconnect packet
-                                                                    let message = server.read_string().await.unwrap();
-                                                                    let message: Chat = serde_json::from_str(&message).unwrap();
-
-                                                                    if let Some(translate) = &message.translate {
-                                                                        if translate.contains("Connection throttled") {
-                                                                            error!("Connection throttle is enabled for {}. Turn it off!", default_server.id);
-                                                                        }   
-                                                                    }
-
-                                                                    stream.write_packet_encrypted(crate::packet::login::Disconnect {
-                                                                            chat: message // directly proxy the disconnect message from destination server
-                                                                    }, &secret).await.unwrap();
-                                                                    return
-                                                                }
-                                                            }
-
-                                                            error!("Failed to connect {} to {}!", player.name, default_server.id);
-                                                       }
-
-                                                   } else {
-                                                       trace!("Failed to connect {} to {}!", player.name, default_server.id);
-                                                       stream.write_packet_encrypted(crate::packet::login::Disconnect {
-                                                            chat: Chat::new(format!("&cFailed to connect to {}!", default_server.id))
-                                                       }, &secret).await.unwrap();
+                                            match crate::protocol::login::attempt_login(config.clone(), &cloned, authenticator.as_ref(), &cloned.players, &mut stream, addr, &handshake).await {
+                                                Ok((player, secret, registration)) => {
+                                                   let name = player.name.to_owned();
+                                                   let result = match secret {
+                                                       Some(secret) => relay::relay(player, registration, CompressedStream::new(EncryptedStream::new(stream, &secret)), default_server, &config, &handshake, &handlers).await,
+                                                       None => relay::relay(player, registration, CompressedStream::new(stream), default_server, &config, &handshake, &handlers).await
+                                                   };
+
+                                                   if let Err(error) = result {
+                                                       debug!("Relay for {} closed with error: {}", name, error);
                                                    }
-
-                                                   /*stream.write_packet_encrypted(crate::packet::login::Success {
-                                                    player: resp.clone()
-                                                    }, &secret).await.unwrap();*/
                                                 },
                 
                                                 Err(error) => {
@@ -255,9 +333,13 @@ where
                                                 }
                                             }
                                         } else {
-                                            stream.write_packet(crate::packet::login::Disconnect {
+                                            let result = stream.write_packet(crate::packet::login::Disconnect {
                                                 chat: Chat::new("&cWe don't know where to send you!")
-                                            }).await.unwrap();
+                                            }, version).await;
+
+                                            if let Err(error) = result {
+                                                debug!("Failed to tell {} there's nowhere to go: {}", addr, error);
+                                            }
 
                                             error!("No default server defined, we don't know where to send player!");
                                         }
@@ -276,21 +358,39 @@ where
             });
         }
 
+        // stdin is read on tokio's blocking pool, so waiting for input never holds up a worker
         tokio::spawn(async move {
-            let stdin = io::stdin();
-            let mut stdin = io::BufReader::new(stdin);
+            let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
             loop {
-                let mut input = String::new();
-                stdin.read_line(&mut input).unwrap();
-                input = input.trim().to_owned();
-                let split = input.split_ascii_whitespace();
-                let cmd = split.clone().next().unwrap().to_lowercase().to_owned();
-                
-                for command in &commands {
-                    if command.get_label().eq(&cmd) ||  command.get_aliases().iter().any(|&i| i.eq(&cmd)) {
-                        command.execute(Box::new(ConsoleCommandSender), split.clone().into_iter().skip(1).map(|f| f.to_owned()).collect())
-                    } else {
-                        println!("Unknown command \"{}\".", &input);
+                match lines.next_line().await {
+                    // a line ending in a tab asks what could come next instead of running it
+                    Ok(Some(line)) if line.ends_with('\t') => {
+                        let line = line.trim_end_matches('\t').trim_start().trim_start_matches('/');
+                        let suggestions = executor.suggest(&ConsoleCommandSender, line);
+                        if !suggestions.is_empty() {
+                            println!("{}", suggestions.join("  "));
+                        }
+                    },
+
+                    Ok(Some(line)) => {
+                        let line = line.trim().trim_start_matches('/');
+                        if line.is_empty() {
+                            continue;
+                        }
+
+                        if !executor.parse(Box::new(ConsoleCommandSender), line) {
+                            println!("Unknown command \"{}\".", line);
+                        }
+                    },
+
+                    Ok(None) => {
+                        debug!("Console input closed, no longer reading commands.");
+                        break;
+                    },
+
+                    Err(error) => {
+                        error!("Failed to read console input: {}", error);
+                        break;
                     }
                 }
             }
@@ -308,7 +408,11 @@ impl CommandSender for ConsoleCommandSender {
         "Console"
     }
 
-    fn send_message(&self, message: String) {
+    fn send_message(&self, message: Chat) {
         info!("{}", message)
     }
+
+    fn is_console(&self) -> bool {
+        true
+    }
 }
\ No newline at end of file
@@ -1,9 +1,639 @@
-pub trait Color {
-    fn colored(&self) -> Self;
+use crate::packet::Chat;
+use crate::packet::chat::{Content, Style, ClickEvent, ClickAction, HoverEvent};
+
+/// The sixteen legacy colors: their code, name and RGB value.
+const COLORS: [(char, &str, u32); 16] = [
+    ('0', "black", 0x000000),
+    ('1', "dark_blue", 0x0000AA),
+    ('2', "dark_green", 0x00AA00),
+    ('3', "dark_aqua", 0x00AAAA),
+    ('4', "dark_red", 0xAA0000),
+    ('5', "dark_purple", 0xAA00AA),
+    ('6', "gold", 0xFFAA00),
+    ('7', "gray", 0xAAAAAA),
+    ('8', "dark_gray", 0x555555),
+    ('9', "blue", 0x5555FF),
+    ('a', "green", 0x55FF55),
+    ('b', "aqua", 0x55FFFF),
+    ('c', "red", 0xFF5555),
+    ('d', "light_purple", 0xFF55FF),
+    ('e', "yellow", 0xFFFF55),
+    ('f', "white", 0xFFFFFF)
+];
+
+/// Turns text with `&` (or `§`) codes into chat components. Besides the sixteen colors, formats
+/// and `&r`, hex colors are understood as `&#RRGGBB` and as `&x&R&R&G&G&B&B` like Spigot writes them.
+///
+/// Colors reset formatting the way they always have.
+pub fn parse(text: &str) -> Chat {
+    let chars: Vec<char> = text.chars().collect();
+
+    let mut components = Vec::new();
+    let mut style = Style::default();
+    let mut current = String::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        if is_prefix(chars[i]) && i + 1 < chars.len() {
+            let code = chars[i + 1].to_ascii_lowercase();
+
+            let change = if let Some(hex) = hex_color(&chars[i + 1..]) {
+                Some((Style { color: Some(hex.0), ..Style::default() }, hex.1))
+            } else if let Some(name) = color_name(code) {
+                Some((Style { color: Some(name.to_owned()), ..Style::default() }, 2))
+            } else if code == 'r' {
+                Some((Style::default(), 2))
+            } else {
+                let mut formatted = style.clone();
+                let format = match code {
+                    'k' => &mut formatted.obfuscated,
+                    'l' => &mut formatted.bold,
+                    'm' => &mut formatted.strikethrough,
+                    'n' => &mut formatted.underlined,
+                    'o' => &mut formatted.italic,
+                    _ => {
+                        current.push(chars[i]);
+                        i += 1;
+                        continue;
+                    }
+                };
+
+                *format = Some(true);
+                Some((formatted, 2))
+            };
+
+            if let Some((changed, length)) = change {
+                if !current.is_empty() {
+                    components.push(styled(std::mem::take(&mut current), &style));
+                }
+
+                style = changed;
+                i += length;
+                continue;
+            }
+        }
+
+        current.push(chars[i]);
+        i += 1;
+    }
+
+    if !current.is_empty() {
+        components.push(styled(current, &style));
+    }
+
+    match components.len() {
+        0 => Chat::text(""),
+        1 => components.remove(0),
+        _ => {
+            let mut root = Chat::text("");
+            root.extra = components;
+            root
+        }
+    }
 }
 
-impl Color for String {
-    fn colored(&self) -> Self {
-        self.replace("&", "§")
+/// Turns tag-based markup into chat components:
+///
+/// - colors as `<gold>`, `<#ff8800>` or `<color:gold>`
+/// - formats as `<bold>`/`<b>`, `<italic>`/`<i>`, `<underlined>`/`<u>`,
+///   `<strikethrough>`/`<st>` and `<obfuscated>`/`<obf>`
+/// - `<gradient:red:#ff8800:...>`, coloring every character of what it encloses
+/// - `<hover:show_text:'...'>`, with more markup as the text
+/// - `<click:run_command:/server lobby>`, or any other click action
+/// - `<reset>` to close everything that's open and `<newline>` for a line break
+///
+/// Tags stay open until closed with `</tag>` or the end of the text. Text in between can still use
+/// `&` codes, and `\<` writes a `<` that doesn't start a tag. Anything that isn't a tag rift knows
+/// is left as text.
+pub fn markup(text: &str) -> Chat {
+    let mut stack = vec![Open {
+        name: String::new(),
+        chat: Chat::text(""),
+        gradient: None
+    }];
+
+    let mut current = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' && rest[1..].starts_with('<') {
+            current.push('<');
+            rest = &rest[2..];
+            continue;
+        }
+
+        if c == '<' {
+            if let Some((tag, length)) = read_tag(rest) {
+                let handled = if let Some(name) = tag.strip_prefix('/') {
+                    let name = canonical(name);
+                    match stack.iter().rposition(|open| open.name == name) {
+                        Some(position) if position > 0 => {
+                            flush(&mut stack, &mut current);
+                            close(&mut stack, position);
+                            true
+                        },
+                        _ => false
+                    }
+                } else {
+                    match open(&tag) {
+                        Some(Tag::Open(open)) => {
+                            flush(&mut stack, &mut current);
+                            stack.push(*open);
+                            true
+                        },
+                        Some(Tag::Reset) => {
+                            flush(&mut stack, &mut current);
+                            close(&mut stack, 1);
+                            true
+                        },
+                        Some(Tag::Newline) => {
+                            current.push('\n');
+                            true
+                        },
+                        None => false
+                    }
+                };
+
+                if handled {
+                    rest = &rest[length..];
+                    continue;
+                }
+            }
+        }
+
+        current.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    flush(&mut stack, &mut current);
+    close(&mut stack, 1);
+
+    stack.pop().map(|open| collapse(open.chat)).unwrap_or_default()
+}
+
+/// A tag that hasn't been closed yet, with everything inside it so far.
+struct Open {
+    name: String,
+    chat: Chat,
+    gradient: Option<Vec<u32>>
+}
+
+enum Tag {
+    Open(Box<Open>),
+    Reset,
+    Newline
+}
+
+fn open(tag: &str) -> Option<Tag> {
+    let arguments = split_arguments(tag);
+    let name = canonical(arguments.first()?);
+
+    let mut chat = Chat::text("");
+    let mut gradient = None;
+
+    match name.as_ref() {
+        "reset" => return Some(Tag::Reset),
+        "newline" => return Some(Tag::Newline),
+        "bold" => chat.style.bold = Some(true),
+        "italic" => chat.style.italic = Some(true),
+        "underlined" => chat.style.underlined = Some(true),
+        "strikethrough" => chat.style.strikethrough = Some(true),
+        "obfuscated" => chat.style.obfuscated = Some(true),
+        "color" => chat.style.color = Some(color(arguments.get(1)?)?),
+        "gradient" => {
+            let stops = arguments[1..].iter()
+                .map(|stop| color(stop).and_then(|color| rgb(&color)))
+                .collect::<Option<Vec<u32>>>()?;
+
+            gradient = match stops.len() {
+                0 => return None,
+                // one color is as good as a gradient between two of the same
+                1 => Some(vec![stops[0], stops[0]]),
+                _ => Some(stops)
+            };
+        },
+        "hover" => match arguments.get(1)?.as_ref() {
+            "show_text" => chat.style.hover_event = Some(HoverEvent::show_text(markup(&arguments[2..].join(":")))),
+            _ => return None
+        },
+        "click" => {
+            let action = match arguments.get(1)?.as_ref() {
+                "open_url" => ClickAction::OpenUrl,
+                "run_command" => ClickAction::RunCommand,
+                "suggest_command" => ClickAction::SuggestCommand,
+                "change_page" => ClickAction::ChangePage,
+                "copy_to_clipboard" => ClickAction::CopyToClipboard,
+                _ => return None
+            };
+
+            // urls are full of colons
+            chat.style.click_event = Some(ClickEvent::new(action, arguments[2..].join(":")));
+        },
+        other => chat.style.color = Some(color(other)?)
+    }
+
+    Some(Tag::Open(Box::new(Open {
+        name,
+        chat,
+        gradient
+    })))
+}
+
+/// Reads the tag `text` starts with, returning what's between the brackets and the length of the
+/// whole tag. Quoted arguments can hold `>`.
+fn read_tag(text: &str) -> Option<(String, usize)> {
+    let mut quote = None;
+
+    for (i, c) in text.char_indices().skip(1) {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('<', None) => return None,
+            ('>', None) if i > 1 => return Some((text[1..i].to_owned(), i + 1)),
+            _ => {}
+        }
     }
-}
\ No newline at end of file
+
+    None
+}
+
+/// Splits a tag on the colons outside quotes, dropping the quotes.
+fn split_arguments(tag: &str) -> Vec<String> {
+    let mut arguments = vec![String::new()];
+    let mut quote = None;
+
+    for c in tag.chars() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            (':', None) => arguments.push(String::new()),
+            (c, _) => arguments.last_mut().unwrap().push(c)
+        }
+    }
+
+    arguments
+}
+
+/// The name a tag is closed with, whichever of its aliases it was opened with.
+fn canonical(name: &str) -> String {
+    let name = name.to_lowercase();
+    let name = name.split(':').next().unwrap_or_default();
+
+    match name {
+        "b" => "bold",
+        "i" | "em" => "italic",
+        "u" | "underline" => "underlined",
+        "st" => "strikethrough",
+        "obf" => "obfuscated",
+        "r" => "reset",
+        "br" => "newline",
+        "colour" | "c" => "color",
+        other => other
+    }.to_owned()
+}
+
+/// A color name or `#rrggbb`, as it's written in components.
+fn color(name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+
+    if name.starts_with('#') {
+        return rgb(&name).map(|_| name);
+    }
+
+    COLORS.iter()
+        .find(|(_, other, _)| *other == name)
+        .map(|(_, name, _)| (*name).to_owned())
+}
+
+fn rgb(color: &str) -> Option<u32> {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 => u32::from_str_radix(hex, 16).ok(),
+        Some(_) => None,
+        None => COLORS.iter()
+            .find(|(_, name, _)| *name == color)
+            .map(|(_, _, rgb)| *rgb)
+    }
+}
+
+/// Adds the text collected so far to the innermost open tag.
+fn flush(stack: &mut [Open], current: &mut String) {
+    if current.is_empty() {
+        return;
+    }
+
+    let text = parse(&std::mem::take(current));
+    if let Some(open) = stack.last_mut() {
+        open.chat.extra.push(text);
+    }
+}
+
+/// Closes every tag from `position` on, innermost first.
+fn close(stack: &mut Vec<Open>, position: usize) {
+    while stack.len() > position {
+        let mut open = stack.pop().unwrap();
+        if let Some(stops) = &open.gradient {
+            let length = count_text(&open.chat);
+            apply_gradient(&mut open.chat, stops, length, &mut 0);
+        }
+
+        if let Some(parent) = stack.last_mut() {
+            parent.chat.extra.push(collapse(open.chat));
+        }
+    }
+}
+
+/// Merges a tag holding nothing but a single component into that component.
+fn collapse(mut chat: Chat) -> Chat {
+    match &chat.content {
+        Content::Text(text) if text.is_empty() && chat.extra.len() == 1 => {
+            let mut child = chat.extra.remove(0);
+            child.style = inherit(&child.style, &chat.style);
+            child
+        },
+        _ => chat
+    }
+}
+
+fn count_text(chat: &Chat) -> usize {
+    let own = match &chat.content {
+        Content::Text(text) => text.chars().count(),
+        _ => 0
+    };
+
+    own + chat.extra.iter().map(count_text).sum::<usize>()
+}
+
+/// Gives every character of text in `chat` its own color along the gradient, `index` counting
+/// the characters colored so far out of `length`.
+fn apply_gradient(chat: &mut Chat, stops: &[u32], length: usize, index: &mut usize) {
+    let mut characters = Vec::new();
+    if let Content::Text(text) = &mut chat.content {
+        for c in std::mem::take(text).chars() {
+            characters.push(Chat::text(c.to_string()).color(format!("#{:06x}", interpolate(stops, *index, length))));
+            *index += 1;
+        }
+    }
+
+    for child in &mut chat.extra {
+        apply_gradient(child, stops, length, index);
+    }
+
+    characters.append(&mut chat.extra);
+    chat.extra = characters;
+}
+
+fn interpolate(stops: &[u32], index: usize, length: usize) -> u32 {
+    let progress = if length > 1 { index as f64 / (length - 1) as f64 } else { 0.0 };
+    let scaled = progress * (stops.len() - 1) as f64;
+
+    let segment = (scaled.floor() as usize).min(stops.len() - 2);
+    let local = scaled - segment as f64;
+    let (from, to) = (stops[segment], stops[segment + 1]);
+
+    let channel = |shift: u32| {
+        let from = (from >> shift & 0xFF) as f64;
+        let to = (to >> shift & 0xFF) as f64;
+        ((from + (to - from) * local).round() as u32) << shift
+    };
+
+    channel(16) | channel(8) | channel(0)
+}
+
+/// Writes `chat` the way clients before JSON chat expect text, with `§` codes. Hex colors become
+/// the closest of the sixteen legacy ones.
+pub fn to_legacy(chat: &Chat) -> String {
+    let mut legacy = String::new();
+    write_legacy(chat, &Style::default(), &mut legacy);
+    legacy
+}
+
+fn write_legacy(chat: &Chat, inherited: &Style, legacy: &mut String) {
+    let style = inherit(&chat.style, inherited);
+
+    let text = chat.content_plain();
+    if !text.is_empty() {
+        match style.color.as_deref().and_then(color_code) {
+            Some(code) => {
+                legacy.push('§');
+                legacy.push(code);
+            },
+            // only needed to get rid of whatever came before
+            None if !legacy.is_empty() => legacy.push_str("§r"),
+            None => {}
+        }
+
+        let formats = [(style.obfuscated, 'k'), (style.bold, 'l'), (style.strikethrough, 'm'), (style.underlined, 'n'), (style.italic, 'o')];
+        for (format, code) in formats.iter() {
+            if *format == Some(true) {
+                legacy.push('§');
+                legacy.push(*code);
+            }
+        }
+
+        legacy.push_str(&text);
+    }
+
+    for child in &chat.extra {
+        write_legacy(child, &style, legacy);
+    }
+}
+
+/// The closest of the sixteen legacy colors to a `#RRGGBB` one, for clients before 1.16. Anything
+/// that isn't a hex color is returned as is.
+pub fn downsample(color: &str) -> String {
+    let rgb = match color.strip_prefix('#').and_then(|hex| u32::from_str_radix(hex, 16).ok()) {
+        Some(rgb) => rgb,
+        None => return color.to_owned()
+    };
+
+    let distance = |other: u32| {
+        let channel = |shift: u32| ((rgb >> shift & 0xFF) as i32 - (other >> shift & 0xFF) as i32).pow(2);
+        channel(16) + channel(8) + channel(0)
+    };
+
+    COLORS.iter()
+        .min_by_key(|(_, _, other)| distance(*other))
+        .map(|(_, name, _)| (*name).to_owned())
+        .unwrap_or_else(|| color.to_owned())
+}
+
+fn inherit(style: &Style, parent: &Style) -> Style {
+    Style {
+        color: style.color.clone().or_else(|| parent.color.clone()),
+        bold: style.bold.or(parent.bold),
+        italic: style.italic.or(parent.italic),
+        underlined: style.underlined.or(parent.underlined),
+        strikethrough: style.strikethrough.or(parent.strikethrough),
+        obfuscated: style.obfuscated.or(parent.obfuscated),
+        font: style.font.clone().or_else(|| parent.font.clone()),
+        insertion: style.insertion.clone().or_else(|| parent.insertion.clone()),
+        click_event: style.click_event.clone().or_else(|| parent.click_event.clone()),
+        hover_event: style.hover_event.clone().or_else(|| parent.hover_event.clone())
+    }
+}
+
+fn styled(text: String, style: &Style) -> Chat {
+    let mut chat = Chat::text(text);
+    chat.style = style.clone();
+    chat
+}
+
+fn is_prefix(c: char) -> bool {
+    c == '&' || c == '§'
+}
+
+fn color_name(code: char) -> Option<&'static str> {
+    COLORS.iter()
+        .find(|(other, _, _)| *other == code)
+        .map(|(_, name, _)| *name)
+}
+
+fn color_code(color: &str) -> Option<char> {
+    let name = downsample(color);
+
+    COLORS.iter()
+        .find(|(_, other, _)| *other == name)
+        .map(|(code, _, _)| *code)
+}
+
+/// Reads a hex color from just after a code prefix, returning it as `#rrggbb` along with how many
+/// characters it took up, the prefix included.
+fn hex_color(chars: &[char]) -> Option<(String, usize)> {
+    let digits: String = match chars.first()?.to_ascii_lowercase() {
+        '#' => chars.get(1..7)?.iter().collect(),
+        'x' => {
+            let pairs = chars.get(1..13)?;
+            if !pairs.chunks(2).all(|pair| is_prefix(pair[0])) {
+                return None;
+            }
+
+            pairs.iter().skip(1).step_by(2).collect()
+        },
+        _ => return None
+    };
+
+    if digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        let length = if chars[0] == '#' { 8 } else { 14 };
+        Some((format!("#{}", digits.to_lowercase()), length))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn json(chat: Chat) -> Value {
+        serde_json::to_value(chat).unwrap()
+    }
+
+    #[test]
+    fn formats_add_to_the_color_before_them() {
+        assert_eq!(json(parse("&cHello &lworld")), json!({"text": "", "extra": [
+            {"text": "Hello ", "color": "red"},
+            {"text": "world", "color": "red", "bold": true}
+        ]}));
+    }
+
+    #[test]
+    fn colors_and_resets_clear_formats() {
+        assert_eq!(json(parse("&lBold&aGreen")), json!({"text": "", "extra": [
+            {"text": "Bold", "bold": true},
+            {"text": "Green", "color": "green"}
+        ]}));
+        assert_eq!(json(parse("&cA&rB")), json!({"text": "", "extra": [
+            {"text": "A", "color": "red"},
+            {"text": "B"}
+        ]}));
+    }
+
+    #[test]
+    fn reads_hex_colors_both_ways() {
+        assert_eq!(json(parse("&#FF8800Orange")), json!({"text": "Orange", "color": "#ff8800"}));
+        assert_eq!(json(parse("&x&f&f&8&8&0&0Orange")), json!({"text": "Orange", "color": "#ff8800"}));
+    }
+
+    #[test]
+    fn takes_section_signs_too() {
+        assert_eq!(json(parse("§aHi")), json!({"text": "Hi", "color": "green"}));
+    }
+
+    #[test]
+    fn leaves_anything_else_as_text() {
+        assert_eq!(json(parse("&zNope & 50%&")), json!({"text": "&zNope & 50%&"}));
+    }
+
+    #[test]
+    fn writes_legacy_codes_back() {
+        assert_eq!(to_legacy(&parse("&cHello &lworld")), "§cHello §c§lworld");
+        assert_eq!(to_legacy(&parse("&cA&rB")), "§cA§rB");
+        // hex colors get the closest of the sixteen
+        assert_eq!(to_legacy(&parse("&#ff8800Orange")), "§6Orange");
+    }
+
+    #[test]
+    fn tags_style_what_they_enclose() {
+        assert_eq!(json(markup("<gold>Hello</gold> world")), json!({"text": "", "extra": [
+            {"text": "Hello", "color": "gold"},
+            {"text": " world"}
+        ]}));
+        assert_eq!(json(markup("<color:#ff8800>x")), json!({"text": "x", "color": "#ff8800"}));
+    }
+
+    #[test]
+    fn tags_close_by_any_of_their_names() {
+        assert_eq!(json(markup("<b>bold</bold> <i>it</em>")), json!({"text": "", "extra": [
+            {"text": "bold", "bold": true},
+            {"text": " "},
+            {"text": "it", "italic": true}
+        ]}));
+    }
+
+    #[test]
+    fn reset_closes_everything_open() {
+        assert_eq!(json(markup("<red>a<bold>b<reset>c")), json!({"text": "", "extra": [
+            {"text": "", "color": "red", "extra": [{"text": "a"}, {"text": "b", "bold": true}]},
+            {"text": "c"}
+        ]}));
+    }
+
+    #[test]
+    fn mixes_with_codes() {
+        assert_eq!(json(markup("<red>&lx")), json!({"text": "x", "color": "red", "bold": true}));
+    }
+
+    #[test]
+    fn handles_events() {
+        assert_eq!(json(markup("<click:run_command:/server lobby>go")),
+            json!({"text": "go", "clickEvent": {"action": "run_command", "value": "/server lobby"}}));
+
+        let hover = json!({"text": "hi", "color": "red"});
+        assert_eq!(json(markup("<hover:show_text:'<red>hi'>x")),
+            json!({"text": "x", "hoverEvent": {"action": "show_text", "contents": hover, "value": hover}}));
+    }
+
+    #[test]
+    fn spreads_gradients_over_every_character() {
+        assert_eq!(json(markup("<gradient:#000000:#ffffff>abc</gradient>")), json!({"text": "", "extra": [
+            {"text": "a", "color": "#000000"},
+            {"text": "b", "color": "#808080"},
+            {"text": "c", "color": "#ffffff"}
+        ]}));
+    }
+
+    #[test]
+    fn leaves_what_is_not_a_tag_as_text() {
+        assert_eq!(json(markup("\\<gold>x")), json!({"text": "<gold>x"}));
+        assert_eq!(json(markup("<nope>x</nope>")), json!({"text": "<nope>x</nope>"}));
+        assert_eq!(json(markup("2 < 3 > 1")), json!({"text": "2 < 3 > 1"}));
+        // only the click actions that are safe to hand out
+        assert_eq!(json(markup("<click:open_file:/etc/passwd>x")), json!({"text": "<click:open_file:/etc/passwd>x"}));
+    }
+
+    #[test]
+    fn writes_newlines() {
+        assert_eq!(json(markup("a<newline>b")), json!({"text": "a\nb"}));
+    }
+}
@@ -1,7 +1,6 @@
 use sha1::{Sha1, Digest};
 use num_bigint::BigInt;
 
-use std::iter;
 
 pub fn server_hash(server_id: &str, secret: &[u8], public_key: &[u8]) -> String {
     let mut hasher = Sha1::new();
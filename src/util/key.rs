@@ -0,0 +1,130 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use log::info;
+use openssl::pkey::Private;
+use openssl::rsa::Rsa;
+
+/// The size the vanilla server uses, clients expect nothing else.
+const KEY_BITS: u32 = 1024;
+
+/// The RSA key clients encrypt their shared secret with, along with its DER-encoded public half
+/// sent in every encryption request.
+pub struct KeyPair {
+    rsa: Rsa<Private>,
+    public_key: Vec<u8>
+}
+
+impl KeyPair {
+    pub fn generate() -> io::Result<Self> {
+        Self::from_rsa(Rsa::generate(KEY_BITS).map_err(to_io)?)
+    }
+
+    /// Reads a PEM-encoded private key.
+    pub fn from_pem(pem: &[u8]) -> io::Result<Self> {
+        Self::from_rsa(Rsa::private_key_from_pem(pem).map_err(to_io)?)
+    }
+
+    fn from_rsa(rsa: Rsa<Private>) -> io::Result<Self> {
+        let public_key = rsa.public_key_to_der().map_err(to_io)?;
+
+        Ok(KeyPair {
+            rsa,
+            public_key
+        })
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::from_pem(&fs::read(path)?)
+    }
+
+    /// Writes the private key to `path` as PEM, readable only by us where that's possible.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let pem = self.rsa.private_key_to_pem().map_err(to_io)?;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        io::Write::write_all(&mut options.open(path)?, &pem)
+    }
+
+    pub fn rsa(&self) -> &Rsa<Private> {
+        &self.rsa
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+}
+
+/// The proxy's current key pair, swapped out whole when rotated so logins already underway finish
+/// with the key they started with.
+pub struct Keys {
+    current: RwLock<Arc<KeyPair>>,
+    path: Option<PathBuf>
+}
+
+impl Keys {
+    /// Loads the key pair at `path`, generating (and saving) one if there's nothing there yet.
+    /// Without a path a fresh key pair is generated that lasts until the proxy stops.
+    pub fn open(path: Option<&Path>) -> io::Result<Self> {
+        let current = match path {
+            Some(path) if path.exists() => {
+                let keys = KeyPair::load(path)?;
+                info!("Loaded RSA key pair from {}.", path.display());
+                keys
+            },
+
+            Some(path) => {
+                let keys = KeyPair::generate()?;
+                keys.save(path)?;
+                info!("Generated a new RSA key pair and saved it to {}.", path.display());
+                keys
+            },
+
+            None => KeyPair::generate()?
+        };
+
+        Ok(Keys {
+            current: RwLock::new(Arc::new(current)),
+            path: path.map(Path::to_path_buf)
+        })
+    }
+
+    pub fn get(&self) -> Arc<KeyPair> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Generates a new key pair and saves it over the old one, so other proxies sharing the file
+    /// can pick it up with [`Keys::reload`].
+    pub fn rotate(&self) -> io::Result<()> {
+        let keys = KeyPair::generate()?;
+        if let Some(path) = &self.path {
+            keys.save(path)?;
+        }
+
+        self.replace(keys);
+        Ok(())
+    }
+
+    /// Reads the key pair from the file again, after another proxy rotated it.
+    pub fn reload(&self) -> io::Result<()> {
+        let path = self.path.as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No key_file configured to reload from."))?;
+
+        self.replace(KeyPair::load(path)?);
+        Ok(())
+    }
+
+    fn replace(&self, keys: KeyPair) {
+        *self.current.write().unwrap() = Arc::new(keys);
+    }
+}
+
+fn to_io(error: openssl::error::ErrorStack) -> io::Error {
+    io::Error::other(error)
+}
@@ -1,2 +1,3 @@
 pub mod color;
-pub mod hash;
\ No newline at end of file
+pub mod hash;
+pub mod key;
\ No newline at end of file